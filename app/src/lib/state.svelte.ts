@@ -3,138 +3,587 @@ import { io, Socket } from "socket.io-client";
 export type Phase = 'placement' | 'waiting' | 'selfturn' | 'otherturn' | 'gameover';
 export type CellType = 'e' | 's' | 'h' | 'm'; // empty, ship, hit, miss
 
+export type Mode = 'classic' | 'salvo';
+export type TurnPolicy = 'bonus_on_hit' | 'alternate' | 'bonus_on_sink';
+export type Difficulty = 'random' | 'hunt' | 'density';
+export type Rules = { width: number, height: number, fleet: number[], mode: Mode, turn_policy: TurnPolicy, bot: Difficulty | null, best_of: number, time_control: TimeControl | null, hints: boolean };
+export type OnTimeout = 'random_shot' | 'forfeit';
+// seconds, no limit where null
+export type TimeControl = { move_limit: number | null, clock: number | null, increment: number, on_timeout: OnTimeout };
+// milliseconds left when sent
+export type Clock = { players: [string | null, string | null], remaining: [number | null, number | null], move_left: number | null };
+export type Score = { players: [string | null, string | null], wins: [number, number], best_of: number };
+export type Heatmap = { heat: number[][], best: [number, number] | null };
+export type Move = { number: number, shooter: string, at: [number, number], hit: boolean, sunk: string | null, time: number };
+export type Substitution = { seat: 1 | 2, player: string, from_move: number };
+export type Replay = {
+    game: number, room: string, players: [string, string], substitutions: Substitution[], boards: [string[], string[]] | null,
+    winner: string | null, started_at: number, ended_at: number | null, moves: Move[]
+};
+export type SpectatorState = {
+    players: [string | null, string | null], boards: [string[], string[]], turn: string | null, game_over: boolean
+};
+// who may join a room, anyone when neither is set
+export type Access = { password: string | null, invite_only: boolean };
+export type Credentials = { password?: string, invite?: string };
+export type LobbyRoom = { code: string, rules: Rules, open_for: number };
+export type Stats = {
+    username: string, display_name: string, games: number, wins: number, win_rate: number | null,
+    average_shots_to_win: number | null, accuracy: number | null, current_streak: number, longest_streak: number,
+    favourite_first_shot: [number, number] | null
+};
+export type Achievement = { id: string, name: string, description: string };
+export type Progress = Achievement & { unlocked_at: number | null };
+export type Account = { id: number, username: string, display_name: string };
+// why the server refused an uploaded fleet, cells as [row, column]
+export type InvalidBoard = { kind: 'missing' | 'malformed' | 'size' | 'cell' | 'bent' | 'touching' | 'fleet', at?: [number, number] };
+export type LeaderboardEntry = { rank: number, username: string, display_name: string, rating: number, games: number };
+export type Leaderboard = { mode: Mode, turn_policy: TurnPolicy, entries: LeaderboardEntry[] };
+export type Reason = 'fleet_sunk' | 'resigned' | 'abandoned' | 'timed_out';
+export type ChatMessage = { from: string, text: string, time: number };
+export type Sunk = { name: string, length: number, bounds: [[number, number], [number, number]] };
+
+export const serverUrl = import.meta.env.DEV ? 'http://localhost:3000' : 'https://battleship.icyground-d91964e0.centralindia.azurecontainerapps.io';
+
+// every achievement, with when the player unlocked it, null for an unknown username
+export async function getAchievements(username: string): Promise<Progress[] | null> {
+    const response = await fetch(`${serverUrl}/api/v1/players/${encodeURIComponent(username)}/achievements`);
+    return response.ok ? await response.json() : null;
+}
+
+// stats of a player by username, null for an unknown username
+export async function getStats(username: string): Promise<Stats | null> {
+    const response = await fetch(`${serverUrl}/api/v1/players/${encodeURIComponent(username)}/stats`);
+    return response.ok ? await response.json() : null;
+}
+
+function describeInvalid(error: InvalidBoard): string {
+    const at = error.at ? ` at row ${error.at[0] + 1}, column ${error.at[1] + 1}` : '';
+    switch (error.kind) {
+        case 'missing': return 'No fleet was sent';
+        case 'malformed': return 'The fleet could not be read';
+        case 'size': return 'The board does not match the room';
+        case 'cell': return `Unknown cell${at}`;
+        case 'bent': return `The ship${at} is not straight`;
+        case 'touching': return `The ship${at} touches another ship`;
+        case 'fleet': return 'The ships do not match the fleet of the room';
+    }
+}
+
 export class State {
     phase: Phase = $state('placement');
     playerBoard = $state(new Board(false));
     opponentBoard = $state(new Board(true));
     users = $state(0);
+    spectators = $state(0);
+    // a spectator sees the first player's fleet as its own and the second's as the opponent's
+    spectating = $state(false);
+    // shown again to watch a restricted room after reconnecting
+    watchCredentials: Credentials | undefined;
+    players: [string | null, string | null] = $state([null, null]);
     room = $state('');
     turn = $state(-1); // -1 not my turn, 0 might be, 1 is
+    message = $state('');
+    chat: ChatMessage[] = $state([]);
+    score: Score | null = $state(null);
+    // 'asked' once this player wants a rematch, 'offered' once the opponent does
+    rematch: 'asked' | 'offered' | null = $state(null);
+    chatError = $state('');
+    rules: Rules = $state(Board.defaultRules);
+    clock: Clock | null = $state(null);
+    // when the clock arrived and whose move it was timing
+    clockAt = 0;
+    mover: string | null = $state(null);
+    // waiting in the queue for a random opponent
+    searching = $state(false);
+    lobby: LobbyRoom[] = $state([]);
+    joinError = $state('');
+    // stable id of this player, the same across reconnects unlike the socket id
+    player = $state('');
+    account: Account | null = $state(null);
+    accountError = $state('');
+    // display names of the signed in players of the room, by player id
+    names: Record<string, string> = $state({});
+    // achievements this player unlocked with the last game
+    unlocked: Achievement[] = $state([]);
+    // why the last fleet was refused, cleared once another is sent
+    boardError = $state('');
+    // answers the server's request for the fleet again, held until the player sends it
+    pendingUpload: ((board: CellType[][]) => void) | null = $state(null);
     socket: Socket;
 
     constructor() {
-        const url = import.meta.env.DEV ? 'ws://localhost:3000' : 'wss://battleship.icyground-d91964e0.centralindia.azurecontainerapps.io';
-        this.socket = io(url, {
+        this.socket = io(serverUrl, {
             transports: ['websocket'],
-            auth: { session: sessionStorage.getItem('session') }
+            auth: (cb) => cb({ token: localStorage.getItem('token') })
         });
 
         this.socket.on('connect', () => {
-            console.log(this.socket.id);
-            sessionStorage.setItem('session', this.socket.id!);
+            if (this.spectating) this.spectateRoom(this.room, this.watchCredentials);
+        });
+
+        this.socket.on('session', ({ player, token, account }: { player: string, token: string, account: Account | null }) => {
+            this.player = player;
+            this.account = account;
+            this.accountError = '';
+            localStorage.setItem('token', token);
+        });
+        this.socket.on('account-error', (code: string) => {
+            this.accountError = code;
         });
 
-        this.socket.on('update-room', ({ room, users }) => {
-            if (this.phase == 'placement') this.phase = 'waiting';
+        this.socket.on('update-room', ({ room, users, spectators, score, names }) => {
+            if (this.phase == 'placement' && !this.boardError) this.phase = 'waiting';
+            this.joinError = '';
             this.room = room;
             this.users = users;
+            this.spectators = spectators;
+            this.score = score;
+            this.names = names;
+        });
+        this.socket.on('join-error', (code: string) => {
+            this.joinError = code;
+        });
+        this.socket.on('spectate-error', (code: string) => {
+            this.joinError = code;
+        });
+        this.socket.on('lobby', ({ rooms }: { rooms: LobbyRoom[] }) => {
+            this.lobby = rooms;
         });
-        this.socket.on('upload', (_, callback) => {
-            if (this.phase == 'gameover') {
-                this.playerBoard.randomize();
-                this.opponentBoard = new Board(true);
+        this.socket.on('queued', () => {
+            this.searching = true;
+        });
+        this.socket.on('matched', () => {
+            this.searching = false;
+            this.message = '';
+        });
+        this.socket.on('match-timeout', () => {
+            this.searching = false;
+            this.message = 'No opponent found, try again later';
+        });
+        this.socket.on('match-error', (code: string) => {
+            console.error('Matchmaking failed', code);
+            this.searching = false;
+            this.message = 'Matchmaking failed, try again';
+        });
+        this.socket.on('rematch-request', () => {
+            this.rematch = 'offered';
+        });
+        this.socket.on('rematch-error', (code: string) => {
+            console.error('Rematch failed', code);
+            this.rematch = null;
+        });
+        // same room, fresh fleets
+        this.socket.on('rematch', () => {
+            this.rematch = null;
+            this.message = '';
+            this.clock = null;
+            this.setRules(this.rules, true);
+            if (this.spectating) this.playerBoard.board = this.playerBoard.empty();
+            this.phase = 'waiting';
+        });
+        this.socket.on('upload', (rules: Rules, callback) => {
+            if (!this.playerBoard.hasRules(rules)) {
+                this.setRules(rules, true);
                 this.phase = 'waiting';
             }
+            // asked again after a rejection, the player places the fleet before it goes out
+            if (this.boardError) {
+                this.pendingUpload = callback;
+                return;
+            }
             callback(this.playerBoard.board);
         });
-        this.socket.on('turnover', (id) => {
-            this.turn = (id == this.socket.id) ? 1 : -1;
-            this.phase = this.turn ? 'selfturn' : 'otherturn';
-        });
-        this.socket.on('attacked', ({ by, at, hit, sunk, game_over }) => {
-            const [i, j]: [number, number] = at;
-            const board = by == this.socket.id ? this.opponentBoard : this.playerBoard;
-            if (by == this.socket.id) {
-                this.turn = (hit) ? 1 : -1;
-            } else {
-                this.turn = (!hit) ? 1 : -1;
-            }
-            if (hit) {
-                board.board[i][j] = 'h';
-                for (const [x, y] of [[-1, -1], [1, 1], [1, -1], [-1, 1]]) {
-                    const [tx, ty] = [i + x, j + y];
-                    if (tx < 0 || tx >= 10 || ty < 0 || ty >= 10) continue;
-                    if (board.board[tx][ty] == 'e')
-                        board.board[tx][ty] = 'm';
-                }
-            } else {
-                board.board[i][j] = 'm';
+        this.socket.on('board-error', (error: InvalidBoard) => {
+            this.boardError = describeInvalid(error);
+            this.phase = 'placement';
+        });
+        // out of attempts, the server has taken this player out of the room
+        this.socket.on('seat-released', () => {
+            this.room = '';
+            this.users = 0;
+            this.score = null;
+            this.phase = 'placement';
+            this.boardError = '';
+            this.pendingUpload = null;
+            this.message = 'Your fleet was rejected too many times, so you left the room';
+        });
+        this.socket.on('turnover', (id, clock: Clock | null) => {
+            this.mover = id;
+            this.setClock(clock);
+            this.turn = (id == this.player) ? 1 : -1;
+            this.phase = this.turn == 1 ? 'selfturn' : 'otherturn';
+        });
+        // whose turn is next arrives in a separate 'turnover', it depends on the room's turn policy
+        this.socket.on('attacked', ({ by, at, hit, sunk, game_over, clock }) => {
+            this.applyShot(by, at, hit, sunk);
+            this.setClock(clock);
+            if (game_over) {
+                this.turn = by == this.player ? 1 : -1;
+                this.phase = 'gameover';
             }
-            if (sunk) {
-                const [[minx, miny], [maxx, maxy]] = sunk;
-                const x1 = Math.max(0, minx - 1);
-                const y1 = Math.max(0, miny - 1);
-                const x2 = Math.min(9, maxx + 1);
-                const y2 = Math.min(9, maxy + 1);
-                for (let x = x1; x <= x2; x++) {
-                    for (let y = y1; y <= y2; y++) {
-                        if (board.board[x][y] == 'e') {
-                            board.board[x][y] = 'm';
-                        }
-                    }
-                }
+        });
+        this.socket.on('salvo', ({ by, shots, game_over, clock }: { by: string, shots: { at: [number, number], hit: boolean, sunk: Sunk | null }[], game_over: boolean, clock: Clock | null }) => {
+            this.setClock(clock);
+            for (const { at, hit, sunk } of shots) {
+                this.applyShot(by, at, hit, sunk);
             }
             if (game_over) {
+                this.turn = by == this.player ? 1 : -1;
                 this.phase = 'gameover';
             }
         });
 
-        this.socket.on('restore', ({ turn, player, opponent, gameover }: { turn: boolean, player: string[], opponent: string[], gameover: boolean }) => {
+        this.socket.on('chat', (message: ChatMessage) => {
+            this.chat.push(message);
+            this.chatError = '';
+        });
+        this.socket.on('chat-error', (code: string) => {
+            this.chatError = code == 'rate_limited' ? 'Slow down, too many messages' : 'Message empty or too long';
+        });
+
+        this.socket.on('game-over', ({ winner, reason }: { winner: string, reason: Reason }) => {
+            this.turn = winner == this.player ? 1 : -1;
+            this.phase = 'gameover';
+            this.unlocked = [];
+            this.mover = null;
+            if (this.spectating) {
+                this.message = `Player ${winner == this.players[0] ? 1 : 2} wins`;
+            } else if (reason == 'resigned') {
+                this.message = this.turn == 1 ? 'Opponent resigned' : 'You resigned';
+            } else if (reason == 'abandoned') {
+                this.message = this.turn == 1 ? 'Opponent left the game' : 'You left the game';
+            } else if (reason == 'timed_out') {
+                this.message = this.turn == 1 ? 'Opponent ran out of time' : 'You ran out of time';
+            }
+        });
+
+        this.socket.on('achievement', ({ player, achievement }: { player: string, achievement: Achievement }) => {
+            if (player == this.player) this.unlocked.push(achievement);
+        });
+
+        this.socket.on('spectating', ({ players, boards, turn, game_over, rules }: SpectatorState & { rules: Rules }) => {
+            this.spectating = true;
+            this.players = players;
+            this.turn = -1;
+            this.phase = game_over ? 'gameover' : turn ? 'otherturn' : 'waiting';
+            this.setRules(rules, true);
+            const toCells = (rows: string[]) => rows.map((s) => s.split('').map(c => c as CellType));
+            if (boards[0].length) this.playerBoard.board = toCells(boards[0]);
+            if (boards[1].length) this.opponentBoard.board = toCells(boards[1]);
+        });
+
+        this.socket.on('restore', ({ turn, player, opponent, game_over, winner, rules, score, messages, clock }: { turn?: boolean, player?: string[], opponent?: string[], game_over?: boolean, winner?: string | null, rules: Rules, score: Score, messages: ChatMessage[], clock: Clock | null }) => {
+            this.score = score;
+            this.setClock(clock);
+            this.chat = messages;
+            // no game yet, waiting for an opponent or for the fleets
+            if (!player || !opponent) {
+                this.setRules(rules, true);
+                this.phase = 'waiting';
+                return;
+            }
             this.turn = turn ? 1 : -1;
-            this.phase = this.turn ? 'selfturn' : 'otherturn';
+            this.phase = this.turn == 1 ? 'selfturn' : 'otherturn';
+            this.mover = turn ? this.player : clock?.players.find((id) => id != this.player) ?? null;
+            this.setRules(rules, true);
             this.playerBoard.board = player.map((s) => s.split('').map(c => c as CellType));
             this.opponentBoard.board = opponent.map((s) => s.split('').map(c => c as CellType));
-            if (gameover) {
+            if (game_over) {
+                this.turn = winner == this.player ? 1 : -1;
                 this.phase = 'gameover';
             }
         })
     }
 
+    applyShot(by: string, [i, j]: [number, number], hit: boolean, sunk: Sunk | null) {
+        const own = by == (this.spectating ? this.players[0] : this.player);
+        const board = own ? this.opponentBoard : this.playerBoard;
+        if (hit) {
+            board.board[i][j] = 'h';
+            for (const [x, y] of [[-1, -1], [1, 1], [1, -1], [-1, 1]]) {
+                const [tx, ty] = [i + x, j + y];
+                if (tx < 0 || tx >= board.height || ty < 0 || ty >= board.width) continue;
+                if (board.board[tx][ty] == 'e')
+                    board.board[tx][ty] = 'm';
+            }
+        } else {
+            board.board[i][j] = 'm';
+        }
+        if (sunk) {
+            const shooter = this.spectating ? `Player ${own ? 1 : 2} sank` : own ? 'You sank' : 'Opponent sank';
+            this.message = `${shooter} the ${sunk.name} (${sunk.length})`;
+            const [[minx, miny], [maxx, maxy]] = sunk.bounds;
+            const x1 = Math.max(0, minx - 1);
+            const y1 = Math.max(0, miny - 1);
+            const x2 = Math.min(board.height - 1, maxx + 1);
+            const y2 = Math.min(board.width - 1, maxy + 1);
+            for (let x = x1; x <= x2; x++) {
+                for (let y = y1; y <= y2; y++) {
+                    if (board.board[x][y] == 'e') {
+                        board.board[x][y] = 'm';
+                    }
+                }
+            }
+        }
+    }
+
+    setRules(rules: Rules, reset = false) {
+        this.rules = rules;
+        if (reset || !this.playerBoard.hasRules(rules)) {
+            this.playerBoard = new Board(false, rules);
+            this.opponentBoard = new Board(true, rules);
+        }
+    }
+
+    setClock(clock: Clock | null) {
+        this.clock = clock;
+        this.clockAt = Date.now();
+    }
+
+    // milliseconds left for this player, or player 1 for spectators, and their opponent,
+    // counting down only for the player to move
+    timeLeft(now: number): [number | null, number | null] {
+        if (!this.clock) return [null, null];
+        const { players, remaining, move_left } = this.clock;
+        const first = this.spectating ? players[0] : this.player;
+        const order = players[1] == first ? [1, 0] : [0, 1];
+        const elapsed = this.phase == 'gameover' ? 0 : now - this.clockAt;
+        return order.map((index) => {
+            if (players[index] != this.mover) return remaining[index];
+            const left = [remaining[index], move_left].filter((t) => t != null);
+            return left.length ? Math.max(0, Math.min(...left) - elapsed) : null;
+        }) as [number | null, number | null];
+    }
+
     attack(i: number, j: number) {
         if (this.turn != 1) return;
         if (this.opponentBoard.board[i][j] != 'e') return;
+        this.opponentBoard.hint = null;
+        if (this.rules.mode == 'salvo') {
+            this.target(i, j);
+            return;
+        }
         this.turn = 0;
 
         this.socket.emit('attack', [i, j]);
     }
 
-    createRoom() {
-        this.socket.emit('create');
+    // salvo targets are collected until there is one for every ship afloat
+    target(i: number, j: number) {
+        const board = this.opponentBoard;
+        const index = board.targets.findIndex(([x, y]) => x == i && y == j);
+        if (index >= 0) {
+            board.targets.splice(index, 1);
+            return;
+        }
+        board.targets.push([i, j]);
+        const unexplored = board.board.flat().filter((c) => c == 'e').length;
+        if (board.targets.length < Math.min(this.playerBoard.shipsAfloat(), unexplored)) return;
+        this.turn = 0;
+        this.socket.emit('salvo', board.targets);
+        board.targets = [];
+    }
+
+    async getHint() {
+        const heatmap: Heatmap | null = await this.socket.emitWithAck('hint');
+        this.opponentBoard.hint = heatmap?.best ?? null;
+    }
+
+    // plays the last game of the room back from the fleets as placed
+    async watchReplay(delay = 400) {
+        const replay: Replay | null = await this.socket.emitWithAck('replay', {});
+        if (!replay?.boards) return;
+        // a seat is played by whoever started in it, then by those who took it over
+        const seatOf = (player: string) =>
+            player == replay.players[1] || replay.substitutions.some((s) => s.seat == 2 && s.player == player) ? 1 : 0;
+        const mine = seatOf(this.player);
+        const toCells = (rows: string[]) => rows.map((s) => s.split('').map(c => c as CellType));
+        this.playerBoard.board = toCells(replay.boards[mine]);
+        this.opponentBoard.board = toCells(replay.boards[1 - mine]);
+        for (const { shooter, at: [i, j], hit } of replay.moves) {
+            await new Promise((resolve) => setTimeout(resolve, delay));
+            const board = seatOf(shooter) == mine ? this.opponentBoard : this.playerBoard;
+            board.board[i][j] = hit ? 'h' : 'm';
+        }
+    }
+
+    createRoom(rules: Rules = Board.defaultRules, isPublic = false, access: Access = { password: null, invite_only: false }) {
+        this.setRules(rules);
+        this.socket.emit('create', { ...rules, public: isPublic, ...access });
+    }
+
+    // a link that seats one more player in this room, even if it is restricted
+    async inviteLink(): Promise<string | null> {
+        const token: string | null = await this.socket.emitWithAck('invite');
+        if (!token) return null;
+        return `${location.origin}${location.pathname}?room=${this.room}&invite=${token}`;
+    }
+
+    // lists the open public rooms, which then stay up to date until a room is created or joined
+    async openLobby() {
+        const lobby: { rooms: LobbyRoom[] } | null = await this.socket.emitWithAck('lobby');
+        this.lobby = lobby?.rooms ?? [];
+    }
+
+    async joinRoom(code: string, credentials?: Credentials) {
+        code = code.toUpperCase();
+        if (code.length != 4 || code == this.room) return;
+        const rules = await this.getRules(code);
+        if (rules) this.setRules(rules);
+        this.socket.emit('join', credentials ? { room: code, ...credentials } : code);
+    }
+
+    register(username: string, password: string, displayName: string) {
+        this.socket.emit('register', { username, password, display_name: displayName.trim() || null });
+    }
+
+    login(username: string, password: string) {
+        this.socket.emit('login', { username, password });
+    }
+
+    logout() {
+        this.socket.emit('logout');
     }
 
-    joinRoom(code: string) {
+    // the best rated players of a variant, or those around this player
+    async getLeaderboard(mode: Mode, turnPolicy: TurnPolicy, aroundMe = false): Promise<LeaderboardEntry[]> {
+        const around = aroundMe ? this.account?.username : undefined;
+        const leaderboard: Leaderboard | null = await this.socket.emitWithAck('leaderboard', { mode, turn_policy: turnPolicy, around });
+        return leaderboard?.entries ?? [];
+    }
+
+    findMatch(rules: Rules) {
+        this.setRules(rules);
+        this.message = '';
+        this.socket.emit('find-match', rules);
+    }
+
+    cancelMatch() {
+        this.searching = false;
+        this.socket.emit('cancel-match');
+    }
+
+    sendChat(text: string) {
+        text = text.trim();
+        if (!text || !this.room) return;
+        this.socket.emit('chat', text);
+    }
+
+    spectateRoom(code: string, credentials?: Credentials) {
         code = code.toUpperCase();
-        if (code.length != 4 || code == this.room && this.phase !== 'gameover') return;
-        this.socket.emit('join', code);
+        if (code.length != 4) return;
+        this.watchCredentials = credentials;
+        this.socket.emit('spectate', credentials ? { room: code, ...credentials } : code);
+    }
+
+    async getRules(code: string): Promise<Rules | null> {
+        return await this.socket.emitWithAck('rules', code);
+    }
+
+    sendFleet() {
+        if (!this.pendingUpload) return;
+        this.pendingUpload(this.playerBoard.board);
+        this.pendingUpload = null;
+        this.boardError = '';
+        this.phase = 'waiting';
     }
 
     hasNotStarted() {
         return this.phase == 'placement' || this.phase == 'waiting';
     }
 
+    resign() {
+        if (this.phase != 'selfturn' && this.phase != 'otherturn') return;
+        this.socket.emit('resign');
+    }
+
     playAgain() {
-        this.joinRoom(this.room);
+        if (this.rematch == 'offered') {
+            this.socket.emit('rematch-accept');
+        } else {
+            this.socket.emit('rematch-request');
+        }
+        this.rematch = 'asked';
+    }
+
+    // wins of this player, or of player 1 for spectators, first
+    wins(): [number, number] {
+        if (!this.score) return [0, 0];
+        const [a, b] = this.score.wins;
+        const first = this.spectating ? this.players[0] : this.player;
+        return this.score.players[1] == first ? [b, a] : [a, b];
     }
 }
 
 
 export class Board {
-    static shipTypes = [5, 4, 3, 3, 2];
-    board: Array<Array<CellType>> = $state(Array.from({ length: 10 }, () => Array.from({ length: 10 }, () => 'e')));
+    static fleets: Record<string, number[]> = {
+        'Classic': [5, 4, 3, 3, 2],
+        'Morskoy Boy': [4, 3, 3, 2, 2, 2, 1, 1, 1, 1],
+    };
+    static defaultRules: Rules = { width: 10, height: 10, fleet: Board.fleets['Classic'], mode: 'classic', turn_policy: 'bonus_on_hit', bot: null, best_of: 5, time_control: null, hints: false };
+    width: number;
+    height: number;
+    fleet: number[];
+    board: Array<Array<CellType>> = $state([]);
+    targets: Array<[number, number]> = $state([]);
+    hint: [number, number] | null = $state(null);
     isOpponent: boolean = false;
 
-    constructor(isOpponent: boolean) {
+    constructor(isOpponent: boolean, rules: Rules = Board.defaultRules) {
         this.isOpponent = isOpponent;
+        this.width = rules.width;
+        this.height = rules.height;
+        this.fleet = rules.fleet;
+        this.board = this.empty();
         if (!isOpponent) this.randomize();
     }
 
+    empty(): Array<Array<CellType>> {
+        return Array.from({ length: this.height }, () => Array.from({ length: this.width }, () => 'e'));
+    }
+
+    hasRules(rules: Rules) {
+        return this.width == rules.width && this.height == rules.height
+            && this.fleet.length == rules.fleet.length && this.fleet.every((ship, i) => ship == rules.fleet[i]);
+    }
+
+    // ships never touch, so every group of adjacent ship cells is one ship
+    shipsAfloat(): number {
+        const seen = this.board.map((row) => row.map(() => false));
+        let afloat = 0;
+        for (let i = 0; i < this.height; i++) {
+            for (let j = 0; j < this.width; j++) {
+                if (seen[i][j] || this.board[i][j] == 'e' || this.board[i][j] == 'm') continue;
+                let intact = false;
+                const queue: Array<[number, number]> = [[i, j]];
+                seen[i][j] = true;
+                while (queue.length) {
+                    const [x, y] = queue.pop()!;
+                    if (this.board[x][y] == 's') intact = true;
+                    for (const [dx, dy] of [[-1, 0], [1, 0], [0, -1], [0, 1]]) {
+                        const [tx, ty] = [x + dx, y + dy];
+                        if (tx < 0 || tx >= this.height || ty < 0 || ty >= this.width || seen[tx][ty]) continue;
+                        if (this.board[tx][ty] == 's' || this.board[tx][ty] == 'h') {
+                            seen[tx][ty] = true;
+                            queue.push([tx, ty]);
+                        }
+                    }
+                }
+                if (intact) afloat++;
+            }
+        }
+        return afloat;
+    }
+
     randomize() {
-        this.board = Array.from({ length: 10 }, () => Array.from({ length: 10 }, () => 'e'));
-        for (const shipLength of Board.shipTypes) {
+        this.board = this.empty();
+        for (const shipLength of [...this.fleet].sort((a, b) => b - a)) {
             while (true) {
                 const dir = Math.round(Math.random());
-                const x = Math.floor(Math.random() * (dir ? 10 : 11 - shipLength));
-                const y = Math.floor(Math.random() * (dir ? (11 - shipLength) : 10));
+                const x = Math.floor(Math.random() * (dir ? this.height : this.height + 1 - shipLength));
+                const y = Math.floor(Math.random() * (dir ? (this.width + 1 - shipLength) : this.width));
                 if (this.isOverlapping(x, y, shipLength, dir)) continue;
                 for (let i = 0; i < shipLength; i++) {
                     this.board[dir ? x : x + i][dir ? y + i : y] = 's';
@@ -148,7 +597,7 @@ export class Board {
         for (let i = -1; i < 2; i++) {
             for (let j = -1; j < length + 1; j++) {
                 const [tx, ty] = [x + (dir ? i : j), y + (dir ? j : i)];
-                if (tx < 0 || tx >= 10 || ty < 0 || ty >= 10) continue;
+                if (tx < 0 || tx >= this.height || ty < 0 || ty >= this.width) continue;
                 if (this.board[tx][ty] != 'e') return true;
             }
         }
@@ -0,0 +1,11 @@
+-- board dimensions are chosen per room at creation
+ALTER TABLE rooms
+ADD COLUMN width SMALLINT DEFAULT 10 NOT NULL,
+    ADD COLUMN height SMALLINT DEFAULT 10 NOT NULL,
+    ADD CONSTRAINT board_size CHECK (
+        width BETWEEN 5 AND 20
+        AND height BETWEEN 5 AND 20
+    );
+
+ALTER TABLE players
+ALTER COLUMN board TYPE TEXT [];
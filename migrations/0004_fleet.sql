@@ -0,0 +1,3 @@
+-- ship lengths each player places, chosen per room at creation
+ALTER TABLE rooms
+ADD COLUMN fleet SMALLINT [] DEFAULT '{5, 4, 3, 3, 2}' NOT NULL;
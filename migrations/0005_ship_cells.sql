@@ -0,0 +1,2 @@
+-- boards record which ship occupies each cell, ships are numbered by their index in rooms.fleet
+COMMENT ON COLUMN players.board IS 'one string per row, ''.'' empty, ''*'' miss, ''A'' + n ship n, ''a'' + n hit on ship n';
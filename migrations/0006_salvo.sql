@@ -0,0 +1,4 @@
+CREATE TYPE MODE AS ENUM ('classic', 'salvo');
+
+ALTER TABLE rooms
+ADD COLUMN mode MODE DEFAULT 'classic' NOT NULL;
@@ -0,0 +1,4 @@
+CREATE TYPE TURN_POLICY AS ENUM ('bonus_on_hit', 'alternate', 'bonus_on_sink');
+
+ALTER TABLE rooms
+ADD COLUMN turn_policy TURN_POLICY DEFAULT 'bonus_on_hit' NOT NULL;
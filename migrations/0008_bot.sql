@@ -0,0 +1,29 @@
+CREATE TYPE DIFFICULTY AS ENUM ('random', 'hunt', 'density');
+
+-- NULL in rooms between two people
+ALTER TABLE rooms
+ADD COLUMN bot DIFFICULTY;
+
+-- delete room if both players are null, the computer (ids starting with '~') leaves with the last person
+CREATE OR REPLACE FUNCTION delete_room() RETURNS TRIGGER AS $$ BEGIN
+DELETE FROM players
+WHERE room_code = OLD.room_code
+    AND id LIKE '~%'
+    AND NOT EXISTS (
+        SELECT 1
+        FROM players
+        WHERE room_code = OLD.room_code
+            AND id NOT LIKE '~%'
+    );
+IF (
+    SELECT player1_id IS NULL
+        AND player2_id IS NULL
+    FROM rooms
+    WHERE code = OLD.room_code
+) THEN
+DELETE FROM rooms
+WHERE code = OLD.room_code;
+END IF;
+RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
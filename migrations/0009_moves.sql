@@ -0,0 +1,29 @@
+-- games outlive their room and players, so players are kept by id only
+CREATE TABLE IF NOT EXISTS games (
+    id SERIAL PRIMARY KEY,
+    room_code CHAR(4) NOT NULL,
+    player1_id CHAR(16) NOT NULL,
+    player2_id CHAR(16) NOT NULL,
+    -- fleets as placed, in the encoding of players.board
+    board1 TEXT [] NOT NULL,
+    board2 TEXT [] NOT NULL,
+    winner CHAR(16),
+    started_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    ended_at TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS moves (
+    game_id INT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    move_number INT NOT NULL,
+    shooter CHAR(16) NOT NULL,
+    i SMALLINT NOT NULL,
+    j SMALLINT NOT NULL,
+    hit BOOLEAN NOT NULL,
+    -- name of the ship this shot sank
+    sunk TEXT,
+    time TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (game_id, move_number)
+);
+
+ALTER TABLE rooms
+ADD COLUMN game_id INT REFERENCES games (id) ON DELETE SET NULL;
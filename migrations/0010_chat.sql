@@ -0,0 +1,10 @@
+-- chat goes away with its room
+CREATE TABLE IF NOT EXISTS messages (
+    id SERIAL PRIMARY KEY,
+    room_code CHAR(4) NOT NULL REFERENCES rooms (code) ON DELETE CASCADE,
+    sender CHAR(16) NOT NULL,
+    text TEXT NOT NULL,
+    time TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS messages_room_code ON messages (room_code, id);
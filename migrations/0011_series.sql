@@ -0,0 +1,12 @@
+-- games won by each player of the room in the current series
+ALTER TABLE rooms
+ADD COLUMN best_of SMALLINT DEFAULT 5 NOT NULL,
+ADD COLUMN score1 SMALLINT DEFAULT 0 NOT NULL,
+ADD COLUMN score2 SMALLINT DEFAULT 0 NOT NULL,
+-- player waiting for the other to accept a rematch
+ADD COLUMN rematch CHAR(16) REFERENCES players (id) ON DELETE
+SET NULL ON UPDATE CASCADE;
+
+-- who had the first turn, the next game of the room starts with the other player
+ALTER TABLE games
+ADD COLUMN starter CHAR(16);
@@ -0,0 +1,12 @@
+CREATE TYPE ON_TIMEOUT AS ENUM ('random_shot', 'forfeit');
+
+-- time controls in seconds, no limit where NULL
+ALTER TABLE rooms
+ADD COLUMN move_limit INT,
+ADD COLUMN clock INT,
+ADD COLUMN increment INT DEFAULT 0 NOT NULL,
+ADD COLUMN on_timeout ON_TIMEOUT DEFAULT 'forfeit' NOT NULL,
+-- milliseconds left on each player's clock when the current move started
+ADD COLUMN clock1 BIGINT,
+ADD COLUMN clock2 BIGINT,
+ADD COLUMN move_started TIMESTAMPTZ;
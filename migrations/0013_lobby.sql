@@ -0,0 +1,7 @@
+-- public rooms are listed in the lobby while they wait for a second player
+ALTER TABLE rooms
+ADD COLUMN public BOOLEAN DEFAULT FALSE NOT NULL,
+ADD COLUMN created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL;
+
+CREATE INDEX idx_public_rooms ON rooms (created_at)
+WHERE public;
@@ -0,0 +1,15 @@
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+-- seats in a room with a password or invites need one of them, bcrypt hashed
+ALTER TABLE rooms
+ADD COLUMN password_hash TEXT,
+ADD COLUMN invite_only BOOLEAN DEFAULT FALSE NOT NULL;
+
+-- single-use invites, by the SHA-256 digest of their token
+CREATE TABLE IF NOT EXISTS invites (
+    token_hash BYTEA PRIMARY KEY,
+    room_code CHAR(4) NOT NULL REFERENCES rooms (code) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX idx_invites_room_code ON invites (room_code);
@@ -0,0 +1,23 @@
+-- optional accounts, bcrypt hashed like room passwords
+CREATE TABLE IF NOT EXISTS accounts (
+    id SERIAL PRIMARY KEY,
+    -- lowercase, for logging in
+    username TEXT NOT NULL UNIQUE,
+    -- shown to other players
+    display_name TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+-- players signed in to an account, outliving the players table as session tokens do
+CREATE TABLE IF NOT EXISTS logins (
+    player_id CHAR(16) PRIMARY KEY,
+    account_id INT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_logins_account_id ON logins (account_id);
+
+-- the people behind the players of a game, when they were signed in
+ALTER TABLE games
+ADD COLUMN account1_id INT REFERENCES accounts (id) ON DELETE SET NULL,
+ADD COLUMN account2_id INT REFERENCES accounts (id) ON DELETE SET NULL;
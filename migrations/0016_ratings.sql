@@ -0,0 +1,28 @@
+-- rooms set up by the matchmaking queue, only their games are rated
+ALTER TABLE rooms
+ADD COLUMN matchmade BOOLEAN DEFAULT FALSE NOT NULL;
+
+-- Elo rating of each account, separately for each variant, a mode with a turn policy
+CREATE TABLE IF NOT EXISTS ratings (
+    account_id INT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    mode MODE NOT NULL,
+    turn_policy TURN_POLICY NOT NULL,
+    rating INT NOT NULL,
+    games INT DEFAULT 0 NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (account_id, mode, turn_policy)
+);
+
+CREATE INDEX idx_ratings_variant_rating ON ratings (mode, turn_policy, rating DESC);
+
+-- every change of a rating, by the game that caused it
+CREATE TABLE IF NOT EXISTS rating_history (
+    account_id INT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    game_id INT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    mode MODE NOT NULL,
+    turn_policy TURN_POLICY NOT NULL,
+    rating_before INT NOT NULL,
+    rating_after INT NOT NULL,
+    time TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (account_id, game_id)
+);
@@ -0,0 +1,22 @@
+-- totals of each account's finished games, kept up to date as games end
+CREATE TABLE IF NOT EXISTS player_stats (
+    account_id INT PRIMARY KEY REFERENCES accounts (id) ON DELETE CASCADE,
+    games INT DEFAULT 0 NOT NULL,
+    wins INT DEFAULT 0 NOT NULL,
+    shots INT DEFAULT 0 NOT NULL,
+    hits INT DEFAULT 0 NOT NULL,
+    -- shots fired in the games that were won
+    winning_shots INT DEFAULT 0 NOT NULL,
+    current_streak INT DEFAULT 0 NOT NULL,
+    longest_streak INT DEFAULT 0 NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT NOW() NOT NULL
+);
+
+-- how often each account opened a game with each cell
+CREATE TABLE IF NOT EXISTS first_shots (
+    account_id INT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    i SMALLINT NOT NULL,
+    j SMALLINT NOT NULL,
+    times INT DEFAULT 0 NOT NULL,
+    PRIMARY KEY (account_id, i, j)
+);
@@ -0,0 +1,10 @@
+-- achievements unlocked by each account, by their id in the catalogue, with the game that did it
+CREATE TABLE IF NOT EXISTS achievements (
+    account_id INT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    achievement TEXT NOT NULL,
+    game_id INT REFERENCES games (id) ON DELETE SET NULL,
+    unlocked_at TIMESTAMPTZ DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (account_id, achievement)
+);
+
+CREATE INDEX idx_achievements_game_id ON achievements (game_id);
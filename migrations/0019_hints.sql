@@ -0,0 +1,3 @@
+-- whether players may ask for the likeliest cells of the opponent's fleet
+ALTER TABLE rooms
+ADD COLUMN hints BOOLEAN DEFAULT FALSE NOT NULL;
@@ -0,0 +1,10 @@
+-- players who took over an abandoned seat in a game in progress, the game keeps the player
+-- who started in the seat
+CREATE TABLE IF NOT EXISTS substitutions (
+    game_id INT NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    seat SMALLINT NOT NULL CHECK (seat IN (1, 2)),
+    substitute CHAR(16) NOT NULL,
+    -- number of the first move after the substitute took the seat
+    from_move INT NOT NULL,
+    PRIMARY KEY (game_id, substitute)
+);
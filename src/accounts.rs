@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Error, Result};
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 20;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// bcrypt ignores anything past 72 bytes.
+pub const MAX_PASSWORD_BYTES: usize = 72;
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+
+/// A person playing under the same name wherever they sign in.
+#[derive(Debug, Clone, Serialize)]
+pub struct Account {
+    pub id: i32,
+    pub username: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Registration {
+    pub username: String,
+    pub password: String,
+    /// The username when not given.
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+/// Usernames are letters, digits and underscores, and match whatever their case.
+fn username(username: &str) -> Result<String> {
+    let username = username.trim().to_lowercase();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&username.chars().count())
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(Error::InvalidUsername);
+    }
+    Ok(username)
+}
+
+fn display_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty()
+        || name.chars().count() > MAX_DISPLAY_NAME_LENGTH
+        || name.chars().any(char::is_control)
+    {
+        return Err(Error::InvalidDisplayName);
+    }
+    Ok(name.to_string())
+}
+
+impl Registration {
+    pub fn validate(&self) -> Result<(String, String)> {
+        let username = username(&self.username)?;
+        if self.password.chars().count() < MIN_PASSWORD_LENGTH
+            || self.password.len() > MAX_PASSWORD_BYTES
+        {
+            return Err(Error::InvalidPassword);
+        }
+        let display_name = display_name(self.display_name.as_deref().unwrap_or(&username))?;
+        Ok((username, display_name))
+    }
+}
+
+/// Creates an account and signs the player in to it.
+pub async fn register(
+    player: &str,
+    registration: &Registration,
+    pool: &sqlx::PgPool,
+) -> Result<Account> {
+    let (username, display_name) = registration.validate()?;
+    let account = sqlx::query_as!(
+        Account,
+        r"INSERT INTO accounts (username, display_name, password_hash) VALUES ($1, $2, crypt($3, gen_salt('bf'))) ON CONFLICT (username) DO NOTHING RETURNING id, username, display_name",
+        username,
+        display_name,
+        registration.password
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::UsernameTaken)?;
+    link(player, account.id, pool).await?;
+    Ok(account)
+}
+
+/// Signs the player in, the same error for an unknown username as for a wrong password.
+pub async fn login(player: &str, login: &Login, pool: &sqlx::PgPool) -> Result<Account> {
+    let account = sqlx::query_as!(
+        Account,
+        r"SELECT id, username, display_name FROM accounts WHERE username = $1 AND password_hash = crypt($2, password_hash)",
+        login.username.trim().to_lowercase(),
+        login.password
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::WrongCredentials)?;
+    link(player, account.id, pool).await?;
+    Ok(account)
+}
+
+/// Ties the player to the account. Games count for the accounts their players were signed in
+/// to when they started, so a game in progress is left as it is.
+async fn link(player: &str, account: i32, pool: &sqlx::PgPool) -> Result<()> {
+    sqlx::query!(
+        r"INSERT INTO logins (player_id, account_id) VALUES ($1, $2) ON CONFLICT (player_id) DO UPDATE SET account_id = $2",
+        player,
+        account
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Signs the player out, games already played stay with the account.
+pub async fn logout(player: &str, pool: &sqlx::PgPool) -> Result<()> {
+    sqlx::query!(r"DELETE FROM logins WHERE player_id = $1", player)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// The account the player is signed in to, if any.
+pub async fn account_of(player: &str, pool: &sqlx::PgPool) -> Result<Option<Account>> {
+    Ok(sqlx::query_as!(
+        Account,
+        r"SELECT a.id, a.username, a.display_name FROM accounts a JOIN logins l ON l.account_id = a.id WHERE l.player_id = $1",
+        player
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+/// Display names of the signed in players of a room, by player id.
+pub async fn display_names(room: &str, pool: &sqlx::PgPool) -> Result<HashMap<String, String>> {
+    Ok(sqlx::query!(
+        r"SELECT p.id, a.display_name FROM players p JOIN logins l ON l.player_id = p.id JOIN accounts a ON a.id = l.account_id WHERE p.room_code = $1",
+        room
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| (r.id, r.display_name))
+    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(username: &str, password: &str, display_name: Option<&str>) -> Registration {
+        Registration {
+            username: username.into(),
+            password: password.into(),
+            display_name: display_name.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn usernames_are_folded_to_lower_case() {
+        assert_eq!(username("Ada_Lovelace").unwrap(), "ada_lovelace");
+        assert_eq!(username("  BOB  ").unwrap(), "bob");
+        assert_eq!(username("P1x").unwrap(), username("p1X").unwrap());
+    }
+
+    #[test]
+    fn usernames_outside_the_bounds_are_refused() {
+        assert!(username(&"a".repeat(MIN_USERNAME_LENGTH)).is_ok());
+        assert!(username(&"a".repeat(MAX_USERNAME_LENGTH)).is_ok());
+        for name in [
+            "a".repeat(MIN_USERNAME_LENGTH - 1),
+            "a".repeat(MAX_USERNAME_LENGTH + 1),
+            // spaces around it don't count towards the length
+            format!("  {}  ", "a".repeat(MIN_USERNAME_LENGTH - 1)),
+        ] {
+            assert!(
+                matches!(username(&name), Err(Error::InvalidUsername)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn usernames_are_letters_digits_and_underscores() {
+        for name in [
+            "ada lovelace",
+            "ada-l",
+            "ada.l",
+            "élodie",
+            "ada!",
+            "名前です",
+        ] {
+            assert!(
+                matches!(username(name), Err(Error::InvalidUsername)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn display_names_are_trimmed_and_bounded() {
+        assert_eq!(display_name("  Ada L.  ").unwrap(), "Ada L.");
+        assert_eq!(display_name("Élodie 名前").unwrap(), "Élodie 名前");
+        assert!(display_name(&"é".repeat(MAX_DISPLAY_NAME_LENGTH)).is_ok());
+        for name in [
+            String::new(),
+            "   ".into(),
+            "é".repeat(MAX_DISPLAY_NAME_LENGTH + 1),
+            "Ada\nLovelace".into(),
+            "Ada\u{7}".into(),
+        ] {
+            assert!(
+                matches!(display_name(&name), Err(Error::InvalidDisplayName)),
+                "{name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn registrations_check_the_password_bounds() {
+        let shortest = "p".repeat(MIN_PASSWORD_LENGTH);
+        assert!(registration("ada", &shortest, None).validate().is_ok());
+        assert!(registration("ada", &"p".repeat(MAX_PASSWORD_BYTES), None)
+            .validate()
+            .is_ok());
+        // characters for the shortest, bytes for the longest
+        assert!(registration("ada", &"é".repeat(MIN_PASSWORD_LENGTH), None)
+            .validate()
+            .is_ok());
+        for password in [
+            "p".repeat(MIN_PASSWORD_LENGTH - 1),
+            "p".repeat(MAX_PASSWORD_BYTES + 1),
+            "é".repeat(MAX_PASSWORD_BYTES / 2 + 1),
+        ] {
+            assert!(
+                matches!(
+                    registration("ada", &password, None).validate(),
+                    Err(Error::InvalidPassword)
+                ),
+                "{password}"
+            );
+        }
+    }
+
+    #[test]
+    fn registrations_default_the_display_name_to_the_username() {
+        let password = "p".repeat(MIN_PASSWORD_LENGTH);
+        assert_eq!(
+            registration("Ada", &password, None).validate().unwrap(),
+            ("ada".into(), "ada".into())
+        );
+        assert_eq!(
+            registration("Ada", &password, Some(" Ada L. "))
+                .validate()
+                .unwrap(),
+            ("ada".into(), "Ada L.".into())
+        );
+        assert!(matches!(
+            registration("a", &password, Some("Ada")).validate(),
+            Err(Error::InvalidUsername)
+        ));
+        assert!(matches!(
+            registration("ada", &password, Some("")).validate(),
+            Err(Error::InvalidDisplayName)
+        ));
+    }
+}
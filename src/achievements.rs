@@ -0,0 +1,417 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::board::{Board, Sunk};
+use crate::game::{Error, Result};
+
+/// One shot of a player as `attack` reported it.
+#[derive(Debug, Clone)]
+pub struct Fired {
+    pub at: (usize, usize),
+    pub hit: bool,
+    pub sunk: Option<Sunk>,
+    pub game_over: bool,
+}
+
+/// A finished game from the side of one of its players.
+#[derive(Debug)]
+pub struct Played {
+    pub won: bool,
+    /// Shots of the player, in order.
+    pub shots: Vec<Fired>,
+    /// Shots of the opponent at the player's fleet, in order.
+    pub taken: Vec<Fired>,
+}
+
+/// An entry of the catalogue, unlocked by any finished game that satisfies its predicate.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Achievement {
+    /// Stable name, as stored.
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    #[serde(skip)]
+    pub unlocked_by: fn(&Played) -> bool,
+}
+
+pub const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        id: "steady_aim",
+        name: "Steady Aim",
+        description: "Win without missing more than 3 times in a row",
+        unlocked_by: |played| played.won && longest_miss_streak(&played.shots) <= 3,
+    },
+    Achievement {
+        id: "big_game_hunter",
+        name: "Big Game Hunter",
+        description: "Sink the Carrier before any other ship",
+        unlocked_by: |played| first_sunk(&played.shots).is_some_and(|sunk| sunk.name == "Carrier"),
+    },
+    Achievement {
+        id: "quick_draw",
+        name: "Quick Draw",
+        description: "Win in under 40 shots",
+        unlocked_by: |played| played.won && played.shots.len() < 40,
+    },
+    Achievement {
+        id: "perfect_game",
+        name: "Perfect Game",
+        description: "Win without a single miss",
+        unlocked_by: |played| played.won && played.shots.iter().all(|shot| shot.hit),
+    },
+    Achievement {
+        id: "untouchable",
+        name: "Untouchable",
+        description: "Win without losing a ship",
+        unlocked_by: |played| played.won && first_sunk(&played.taken).is_none(),
+    },
+    Achievement {
+        id: "grand_finale",
+        name: "Grand Finale",
+        description: "End the game by sinking the Carrier",
+        unlocked_by: |played| {
+            played.shots.last().is_some_and(|shot| {
+                shot.game_over
+                    && shot
+                        .sunk
+                        .as_ref()
+                        .is_some_and(|sunk| sunk.name == "Carrier")
+            })
+        },
+    },
+    Achievement {
+        id: "surgical_strike",
+        name: "Surgical Strike",
+        description: "Sink the Carrier without a miss from its first hit to its sinking",
+        unlocked_by: |played| {
+            played.shots.iter().enumerate().any(|(k, shot)| {
+                shot.sunk.as_ref().is_some_and(|sunk| {
+                    sunk.name == "Carrier" && {
+                        let first_hit = played.shots[..k]
+                            .iter()
+                            .position(|shot| shot.hit && within(shot.at, sunk.bounds))
+                            .unwrap_or(k);
+                        played.shots[first_hit..k].iter().all(|shot| shot.hit)
+                    }
+                })
+            })
+        },
+    },
+];
+
+fn within((i, j): (usize, usize), [from, to]: [(usize, usize); 2]) -> bool {
+    (from.0..=to.0).contains(&i) && (from.1..=to.1).contains(&j)
+}
+
+fn longest_miss_streak(shots: &[Fired]) -> usize {
+    shots
+        .split(|shot| shot.hit)
+        .map(|misses| misses.len())
+        .max()
+        .unwrap_or(0)
+}
+
+fn first_sunk(shots: &[Fired]) -> Option<&Sunk> {
+    shots.iter().find_map(|shot| shot.sunk.as_ref())
+}
+
+pub fn find(id: &str) -> Option<&'static Achievement> {
+    ACHIEVEMENTS.iter().find(|achievement| achievement.id == id)
+}
+
+/// An achievement of the catalogue, with when an account unlocked it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Progress {
+    #[serde(flatten)]
+    pub achievement: &'static Achievement,
+    /// Milliseconds since the epoch, `None` while locked.
+    pub unlocked_at: Option<i64>,
+}
+
+/// An achievement unlocked by a player of a room in its last game.
+#[derive(Debug)]
+pub struct Unlocked {
+    pub player: String,
+    pub achievement: &'static Achievement,
+}
+
+/// Plays a finished game again from the fleets as placed and its moves, then stores the
+/// achievements each account that played it unlocked for the first time.
+pub async fn unlock_achievements(conn: &mut sqlx::PgConnection, game: i32) -> Result<()> {
+    let Some(game) = sqlx::query!(
+        r"SELECT g.id, g.player1_id, g.player2_id, g.account1_id, g.account2_id, g.winner, g.board1, g.board2, r.fleet FROM games g JOIN rooms r ON r.game_id = g.id WHERE g.id = $1",
+        game
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(());
+    };
+    let moves = sqlx::query!(
+        r"SELECT shooter, i, j FROM moves WHERE game_id = $1 ORDER BY move_number",
+        game.id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let fleet: Vec<usize> = game.fleet.iter().map(|&len| len as usize).collect();
+    // each player shoots at the fleet of the other
+    let mut targets = [Board::from(game.board2), Board::from(game.board1)];
+    let mut fired: [Vec<Fired>; 2] = Default::default();
+    for shot in moves {
+        let side = usize::from(shot.shooter != game.player1_id);
+        let target = &mut targets[side];
+        let at = (shot.i as usize, shot.j as usize);
+        let Some(hit) = target.fire(at) else {
+            continue;
+        };
+        fired[side].push(Fired {
+            at,
+            hit,
+            sunk: hit.then(|| target.has_sunk(at, &fleet)).flatten(),
+            game_over: target.is_game_over(&fleet),
+        });
+    }
+
+    let [shots1, shots2] = fired;
+    let sides = [
+        (
+            game.account1_id,
+            Played {
+                won: game.winner.as_ref() == Some(&game.player1_id),
+                shots: shots1.clone(),
+                taken: shots2.clone(),
+            },
+        ),
+        (
+            game.account2_id,
+            Played {
+                won: game.winner.as_ref() == Some(&game.player2_id),
+                shots: shots2,
+                taken: shots1,
+            },
+        ),
+    ];
+    for (account, played) in sides {
+        let Some(account) = account else {
+            continue;
+        };
+        for achievement in ACHIEVEMENTS
+            .iter()
+            .filter(|achievement| (achievement.unlocked_by)(&played))
+        {
+            sqlx::query!(
+                r"INSERT INTO achievements (account_id, achievement, game_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                account,
+                achievement.id,
+                game.id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Achievements unlocked for the first time in the room's last game.
+pub async fn unlocked_in(room: &str, pool: &sqlx::PgPool) -> Result<Vec<Unlocked>> {
+    Ok(sqlx::query!(
+        r#"SELECT CASE WHEN a.account_id = g.account1_id THEN g.player1_id ELSE g.player2_id END AS "player!", a.achievement FROM achievements a JOIN games g ON g.id = a.game_id JOIN rooms r ON r.game_id = g.id WHERE r.code = $1"#,
+        room
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .filter_map(|r| {
+        Some(Unlocked {
+            player: r.player,
+            achievement: find(&r.achievement)?,
+        })
+    })
+    .collect())
+}
+
+/// The whole catalogue, with what the account has unlocked so far.
+pub async fn achievements_of(username: &str, pool: &sqlx::PgPool) -> Result<Vec<Progress>> {
+    let account = sqlx::query_scalar!(
+        r"SELECT id FROM accounts WHERE username = $1",
+        username.trim().to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::AccountNotFound)?;
+    let unlocked = sqlx::query!(
+        r#"SELECT achievement, (EXTRACT(EPOCH FROM unlocked_at) * 1000)::BIGINT AS "unlocked_at!" FROM achievements WHERE account_id = $1"#,
+        account
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(ACHIEVEMENTS
+        .iter()
+        .map(|achievement| Progress {
+            achievement,
+            unlocked_at: unlocked
+                .iter()
+                .find(|r| r.achievement == achievement.id)
+                .map(|r| r.unlocked_at),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARRIER: [(usize, usize); 2] = [(0, 0), (0, 4)];
+    const DESTROYER: [(usize, usize); 2] = [(9, 0), (9, 1)];
+
+    fn miss(at: (usize, usize)) -> Fired {
+        Fired {
+            at,
+            hit: false,
+            sunk: None,
+            game_over: false,
+        }
+    }
+
+    fn hit(at: (usize, usize)) -> Fired {
+        Fired {
+            hit: true,
+            ..miss(at)
+        }
+    }
+
+    fn sink(at: (usize, usize), name: &'static str, bounds: [(usize, usize); 2]) -> Fired {
+        Fired {
+            sunk: Some(Sunk {
+                name,
+                length: bounds[1].1 - bounds[0].1 + 1,
+                bounds,
+            }),
+            ..hit(at)
+        }
+    }
+
+    /// Hits along the Carrier until it sinks.
+    fn carrier() -> Vec<Fired> {
+        let mut shots: Vec<Fired> = (0..4).map(|j| hit((0, j))).collect();
+        shots.push(sink((0, 4), "Carrier", CARRIER));
+        shots
+    }
+
+    fn destroyer() -> Vec<Fired> {
+        vec![hit((9, 0)), sink((9, 1), "Destroyer", DESTROYER)]
+    }
+
+    fn won(shots: Vec<Fired>) -> Played {
+        Played {
+            won: true,
+            shots,
+            taken: vec![],
+        }
+    }
+
+    fn lost(shots: Vec<Fired>) -> Played {
+        Played {
+            won: false,
+            ..won(shots)
+        }
+    }
+
+    fn unlocks(id: &str, played: &Played) -> bool {
+        (find(id).unwrap().unlocked_by)(played)
+    }
+
+    fn misses(count: usize) -> Vec<Fired> {
+        (0..count).map(|j| miss((5, j))).collect()
+    }
+
+    #[test]
+    fn catalogue_ids_are_unique() {
+        for (k, achievement) in ACHIEVEMENTS.iter().enumerate() {
+            assert!(ACHIEVEMENTS[..k].iter().all(|a| a.id != achievement.id));
+        }
+        assert!(find("no_such_thing").is_none());
+    }
+
+    #[test]
+    fn steady_aim_allows_three_misses_in_a_row() {
+        let three = [misses(3), vec![hit((0, 0))], misses(3), carrier()].concat();
+        let four = [misses(4), carrier()].concat();
+        assert!(unlocks("steady_aim", &won(three.clone())));
+        assert!(!unlocks("steady_aim", &won(four)));
+        assert!(!unlocks("steady_aim", &lost(three)));
+    }
+
+    #[test]
+    fn big_game_hunter_needs_the_carrier_first() {
+        assert!(unlocks(
+            "big_game_hunter",
+            &lost([carrier(), destroyer()].concat())
+        ));
+        assert!(!unlocks(
+            "big_game_hunter",
+            &won([destroyer(), carrier()].concat())
+        ));
+        assert!(!unlocks("big_game_hunter", &won(misses(5))));
+    }
+
+    #[test]
+    fn quick_draw_needs_a_win_under_40_shots() {
+        let shots = |count: usize| [misses(count - 5), carrier()].concat();
+        assert!(unlocks("quick_draw", &won(shots(39))));
+        assert!(!unlocks("quick_draw", &won(shots(40))));
+        assert!(!unlocks("quick_draw", &lost(shots(10))));
+    }
+
+    #[test]
+    fn perfect_game_needs_a_win_without_misses() {
+        assert!(unlocks("perfect_game", &won(carrier())));
+        assert!(!unlocks(
+            "perfect_game",
+            &won([misses(1), carrier()].concat())
+        ));
+        assert!(!unlocks("perfect_game", &lost(carrier())));
+    }
+
+    #[test]
+    fn untouchable_needs_a_win_with_the_fleet_afloat() {
+        let hit_but_afloat = Played {
+            taken: vec![hit((0, 0)), miss((1, 1))],
+            ..won(carrier())
+        };
+        let lost_a_ship = Played {
+            taken: destroyer(),
+            ..won(carrier())
+        };
+        assert!(unlocks("untouchable", &hit_but_afloat));
+        assert!(!unlocks("untouchable", &lost_a_ship));
+        assert!(!unlocks("untouchable", &lost(carrier())));
+    }
+
+    #[test]
+    fn grand_finale_needs_the_carrier_to_end_the_game() {
+        let mut last_carrier = [destroyer(), carrier()].concat();
+        last_carrier.last_mut().unwrap().game_over = true;
+        let mut last_destroyer = [carrier(), destroyer()].concat();
+        last_destroyer.last_mut().unwrap().game_over = true;
+        assert!(unlocks("grand_finale", &won(last_carrier)));
+        assert!(!unlocks("grand_finale", &won(last_destroyer)));
+        // sinking it without ending the game
+        assert!(!unlocks(
+            "grand_finale",
+            &won([destroyer(), carrier()].concat())
+        ));
+    }
+
+    #[test]
+    fn surgical_strike_needs_no_miss_from_the_first_carrier_hit() {
+        let misses_before = [misses(3), carrier()].concat();
+        let other_hits_between = [vec![hit((0, 0))], destroyer(), carrier()[1..].to_vec()].concat();
+        let miss_between = [vec![hit((0, 0)), miss((5, 5))], carrier()[1..].to_vec()].concat();
+        assert!(unlocks("surgical_strike", &won(misses_before)));
+        assert!(unlocks("surgical_strike", &lost(other_hits_between)));
+        assert!(!unlocks("surgical_strike", &won(miss_between)));
+        assert!(!unlocks("surgical_strike", &won(destroyer())));
+    }
+}
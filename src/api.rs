@@ -0,0 +1,231 @@
+use axum::body::Bytes;
+use axum::extract::rejection::{PathRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use schemars::JsonSchema;
+use serde::Serialize;
+use socketioxide::SocketIo;
+use sqlx::PgPool;
+
+use crate::achievements::{achievements_of, Progress};
+use crate::game::{
+    add_room, delete_sid, game_summary, get_replay, in_delete_sid, make_public,
+    room_if_player_exists, room_info, Error, GameSummary, Replay, RoomInfo,
+};
+use crate::openapi;
+use crate::ratings::{leaderboard, Leaderboard, LeaderboardQuery};
+use crate::session::{new_player_id, Sessions};
+use crate::stats::{get_stats, Stats};
+use crate::{emit_lobby, NewRoom, FORFEIT_GRACE, LOBBY};
+
+/// What the handlers share with the socket side.
+#[derive(Clone)]
+pub struct Api {
+    pub pool: PgPool,
+    pub sessions: Sessions,
+    pub io: SocketIo,
+}
+
+/// Routes of version 1 of the API, to be nested under `/api/v1`.
+pub fn router(api: Api) -> Router {
+    Router::new()
+        .route("/rooms", post(create_room))
+        .route("/rooms/:code", get(get_room_info))
+        .route("/games/:id", get(get_game))
+        .route("/games/:id/replay", get(get_game_replay))
+        .route("/leaderboard", get(get_leaderboard))
+        .route("/players/:username/stats", get(get_player_stats))
+        .route(
+            "/players/:username/achievements",
+            get(get_player_achievements),
+        )
+        .route("/openapi.json", get(get_openapi))
+        .with_state(api)
+}
+
+/// Body of every failed request.
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "Error")]
+pub struct ErrorBody {
+    #[schemars(schema_with = "openapi::error_code")]
+    pub code: &'static str,
+    pub message: String,
+}
+
+pub fn status(error: &Error) -> StatusCode {
+    match error {
+        Error::RoomNotFound | Error::GameNotFound | Error::AccountNotFound => StatusCode::NOT_FOUND,
+        Error::InvalidRules
+        | Error::InvalidMove
+        | Error::WrongMode(_)
+        | Error::InvalidMessage
+        | Error::InvalidPassword
+        | Error::InvalidUsername
+        | Error::InvalidDisplayName
+        | Error::InvalidRequest
+        | Error::InvalidBoard(_) => StatusCode::BAD_REQUEST,
+        Error::NotInRoom | Error::WrongCredentials | Error::HintsDisabled => StatusCode::FORBIDDEN,
+        Error::RoomFull(_)
+        | Error::RoomNotFull
+        | Error::NotYourTurn
+        | Error::GameOverRoom
+        | Error::AlreadyInRoom
+        | Error::GameNotOver
+        | Error::NoGameInProgress
+        | Error::NoRematchRequest
+        | Error::UsernameTaken => StatusCode::CONFLICT,
+        Error::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        Error::CodeGenerationLimitReached => StatusCode::SERVICE_UNAVAILABLE,
+        Error::Sqlx(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = status(&self);
+        let message = match &self {
+            Error::Sqlx(_) => {
+                tracing::error!("{:?}", self);
+                "Internal error".to_string()
+            }
+            e => e.to_string(),
+        };
+        let body = ErrorBody {
+            code: self.code(),
+            message,
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// A room created over HTTP, with the session of the player seated in it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Created {
+    pub room: String,
+    pub player: String,
+    /// Connecting to the socket with this token takes the seat.
+    pub token: String,
+}
+
+/// The player of a bearer token this server issued, a new player otherwise.
+fn player_of(headers: &HeaderMap, sessions: &Sessions) -> String {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| sessions.verify(token))
+        .unwrap_or_else(new_player_id)
+}
+
+async fn create_room(
+    State(api): State<Api>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Created>), Error> {
+    // an empty body asks for the defaults, as on the socket
+    let NewRoom {
+        rules,
+        public,
+        access,
+    } = if body.is_empty() {
+        NewRoom::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|_| Error::InvalidRequest)?
+    };
+    // the computer only plays sockets
+    if rules.bot.is_some() {
+        return Err(Error::InvalidRules);
+    }
+    let player = player_of(&headers, &api.sessions);
+    if room_if_player_exists(&player, &api.pool).await?.is_some() {
+        return Err(Error::AlreadyInRoom);
+    }
+
+    let room = add_room(&player, &rules, &access, false, &api.pool).await?;
+    tracing::info!("Creating room over HTTP: {:?}", room);
+    if public && !access.is_restricted() {
+        make_public(&room, &api.pool).await?;
+        emit_lobby(api.io.within(LOBBY), &api.pool).await;
+    }
+    tokio::spawn(release_if_unclaimed(player.clone(), api.clone()));
+    Ok((
+        StatusCode::CREATED,
+        Json(Created {
+            room,
+            token: api.sessions.issue(&player),
+            player,
+        }),
+    ))
+}
+
+/// Frees the seat of a room created over HTTP if nobody has connected with its token in time.
+/// Once a socket has played as the player, its disconnect takes care of the seat instead.
+async fn release_if_unclaimed(player: String, api: Api) {
+    tokio::time::sleep(FORFEIT_GRACE).await;
+    if api.sessions.is_attached(&player) || in_delete_sid(&player, &api.pool).await.unwrap_or(true)
+    {
+        return;
+    }
+    tracing::info!("Releasing unclaimed seat: {:?}", player);
+    if let Err(e) = delete_sid(&player, &api.pool).await {
+        tracing::error!("{:?}", e);
+    }
+    emit_lobby(api.io.within(LOBBY), &api.pool).await;
+}
+
+async fn get_room_info(
+    State(api): State<Api>,
+    Path(code): Path<String>,
+) -> Result<Json<RoomInfo>, Error> {
+    Ok(Json(room_info(&code.to_uppercase(), &api.pool).await?))
+}
+
+/// No game has an id that isn't a number.
+fn game_id(id: Result<Path<i32>, PathRejection>) -> Result<i32, Error> {
+    id.map(|Path(id)| id).map_err(|_| Error::GameNotFound)
+}
+
+async fn get_game(
+    State(api): State<Api>,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Json<GameSummary>, Error> {
+    Ok(Json(game_summary(game_id(id)?, &api.pool).await?))
+}
+
+async fn get_game_replay(
+    State(api): State<Api>,
+    headers: HeaderMap,
+    id: Result<Path<i32>, PathRejection>,
+) -> Result<Json<Replay>, Error> {
+    let viewer = player_of(&headers, &api.sessions);
+    Ok(Json(get_replay(game_id(id)?, &viewer, &api.pool).await?))
+}
+
+async fn get_leaderboard(
+    State(api): State<Api>,
+    query: Result<Query<LeaderboardQuery>, QueryRejection>,
+) -> Result<Json<Leaderboard>, Error> {
+    let Query(query) = query.map_err(|_| Error::InvalidRequest)?;
+    Ok(Json(leaderboard(&query, &api.pool).await?))
+}
+
+async fn get_player_stats(
+    State(api): State<Api>,
+    Path(username): Path<String>,
+) -> Result<Json<Stats>, Error> {
+    Ok(Json(get_stats(&username, &api.pool).await?))
+}
+
+async fn get_player_achievements(
+    State(api): State<Api>,
+    Path(username): Path<String>,
+) -> Result<Json<Vec<Progress>>, Error> {
+    Ok(Json(achievements_of(&username, &api.pool).await?))
+}
+
+async fn get_openapi() -> Json<serde_json::Value> {
+    Json(openapi::document())
+}
@@ -1,32 +1,84 @@
+pub mod density;
+
 use std::ops::{Deref, DerefMut};
 
-use axum::Json;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use thiserror::Error;
+
+/// A board as the client draws it, one char per cell: 'e'mpty, 's'hip, 'h'it or 'm'iss.
+pub type Layout = Vec<Vec<char>>;
+
+/// Ships are identified by their index in the room's fleet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell {
+    Empty,
+    Miss,
+    Ship(u8),
+    Hit(u8),
+}
+
+impl Cell {
+    /// Stored in Postgres as one char per cell, ships as 'A' + id and their hits as 'a' + id.
+    fn encode(self) -> char {
+        match self {
+            Cell::Empty => '.',
+            Cell::Miss => '*',
+            Cell::Ship(id) => (b'A' + id) as char,
+            Cell::Hit(id) => (b'a' + id) as char,
+        }
+    }
+
+    fn decode(c: char) -> Self {
+        match c {
+            'A'..='Z' => Cell::Ship(c as u8 - b'A'),
+            'a'..='z' => Cell::Hit(c as u8 - b'a'),
+            '*' => Cell::Miss,
+            _ => Cell::Empty,
+        }
+    }
+
+    /// What the client draws, ships are only drawn if revealed.
+    fn view(self, reveal: bool) -> char {
+        match self {
+            Cell::Empty => 'e',
+            Cell::Miss => 'm',
+            Cell::Ship(_) if reveal => 's',
+            Cell::Ship(_) => 'e',
+            Cell::Hit(_) => 'h',
+        }
+    }
+
+    fn ship(self) -> Option<u8> {
+        match self {
+            Cell::Ship(id) | Cell::Hit(id) => Some(id),
+            _ => None,
+        }
+    }
+}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Board(pub [[char; 10]; 10]);
+#[derive(Debug)]
+pub struct Board(pub Vec<Vec<Cell>>);
 
 impl From<Board> for Vec<String> {
     fn from(board: Board) -> Self {
-        board.iter().map(|row| row.iter().collect()).collect()
+        (0..board.height()).map(|i| board.encode_row(i)).collect()
     }
 }
 
 impl From<Vec<String>> for Board {
     fn from(board: Vec<String>) -> Self {
-        let mut arr = [['e'; 10]; 10];
-        for (i, row) in board.iter().enumerate() {
-            for (j, cell) in row.chars().enumerate() {
-                arr[i][j] = cell;
-            }
-        }
-        Board(arr)
+        Board(
+            board
+                .iter()
+                .map(|row| row.chars().map(Cell::decode).collect())
+                .collect(),
+        )
     }
 }
 
 impl Deref for Board {
-    type Target = [[char; 10]; 10];
+    type Target = Vec<Vec<Cell>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -39,41 +91,226 @@ impl DerefMut for Board {
     }
 }
 
+/// Why an uploaded board was rejected, sent back to the client as is.
+#[derive(Debug, Error, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Invalid {
+    #[error("No board uploaded")]
+    Missing,
+    #[error("Board is not a list of rows of cells")]
+    Malformed,
+    #[error("Board must be {height}x{width}")]
+    Size { height: usize, width: usize },
+    #[error("Unknown cell {cell:?} at {at:?}")]
+    Cell { at: (usize, usize), cell: char },
+    #[error("Ship at {at:?} is not straight")]
+    Bent { at: (usize, usize) },
+    #[error("Ship at {at:?} touches another ship")]
+    Touching { at: (usize, usize) },
+    #[error("Fleet must be {expected:?}, found {found:?}")]
+    Fleet {
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
+}
+
+/// A ship that was sunk by the last attack.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sunk {
+    pub name: &'static str,
+    pub length: usize,
+    pub bounds: [(usize, usize); 2],
+}
+
+pub const CLASSIC_FLEET: [usize; 5] = [5, 4, 3, 3, 2];
+
+/// Name of the ship at `id` in the fleet, a second ship of length 3 is the Submarine.
+pub fn ship_name(fleet: &[usize], id: usize) -> &'static str {
+    match fleet[id] {
+        1 => "Patrol Boat",
+        2 => "Destroyer",
+        3 if fleet[..id].contains(&3) => "Submarine",
+        3 => "Cruiser",
+        4 => "Battleship",
+        5 => "Carrier",
+        _ => "Dreadnought",
+    }
+}
+
 impl Board {
-    const SHIPS: [i32; 5] = [5, 4, 3, 3, 2];
+    // attempts per ship before the whole layout is thrown away, and layouts before giving up
+    const PLACEMENT_ATTEMPTS: usize = 100;
+    const LAYOUT_ATTEMPTS: usize = 100;
+    // positions tried by `fit` before a fleet is taken not to fit
+    const FIT_STEPS: usize = 200_000;
+
+    pub fn new(height: usize, width: usize) -> Self {
+        Board(vec![vec![Cell::Empty; width]; height])
+    }
+
+    pub fn height(&self) -> usize {
+        self.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.first().map_or(0, |row| row.len())
+    }
 
-    pub fn from_json(Json(board): Json<Board>) -> Self {
+    pub fn contains(&self, (i, j): (usize, usize)) -> bool {
+        i < self.height() && j < self.width()
+    }
+
+    pub fn encode_row(&self, i: usize) -> String {
+        self[i].iter().map(|cell| cell.encode()).collect()
+    }
+
+    /// Rows as the client draws them, hiding ships that have not been hit unless revealed.
+    pub fn view(&self, reveal: bool) -> Vec<String> {
+        self.iter()
+            .map(|row| row.iter().map(|cell| cell.view(reveal)).collect())
+            .collect()
+    }
+
+    /// Places the fleet at random, or as `fit` does, flipped at random, when it is too tight for
+    /// that. Returns `None` only if it does not fit on the given dimensions.
+    pub fn randomize(height: usize, width: usize, fleet: &[usize]) -> Option<Self> {
+        let ships = Self::longest_first(fleet);
+        'layout: for _ in 0..Self::LAYOUT_ATTEMPTS {
+            let mut board = Board::new(height, width);
+            for &id in ships.iter() {
+                if !board.place_random(id as u8, fleet[id] as i32) {
+                    continue 'layout;
+                }
+            }
+            return Some(board);
+        }
+        Self::fit(height, width, fleet).map(Self::shuffled)
+    }
+
+    /// The same layout mirrored either way and, on a square board, turned over its diagonal, at
+    /// random.
+    fn shuffled(mut self) -> Self {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(0.5) {
+            self.reverse();
+        }
+        if rng.gen_bool(0.5) {
+            self.iter_mut().for_each(|row| row.reverse());
+        }
+        if self.height() == self.width() && rng.gen_bool(0.5) {
+            self = Board(
+                (0..self.width())
+                    .map(|j| self.iter().map(|row| row[j]).collect())
+                    .collect(),
+            );
+        }
+        self
+    }
+
+    /// The first layout of the fleet found by trying every position in order, `None` if there is
+    /// none. The same dimensions and fleet always get the same answer.
+    pub fn fit(height: usize, width: usize, fleet: &[usize]) -> Option<Self> {
+        // a ship with the row below it and the column after it covers 2 x (length + 1) cells of a
+        // board one larger each way, and ships that don't touch never share any of them
+        let area: usize = fleet.iter().map(|&length| 2 * (length + 1)).sum();
+        if area > (height + 1) * (width + 1) {
+            return None;
+        }
+        let mut board = Board::new(height, width);
+        let mut steps = Self::FIT_STEPS;
         board
+            .place_from(&Self::longest_first(fleet), fleet, 0, &mut steps)
+            .then_some(board)
     }
 
-    pub fn randomize() -> Self {
-        let mut board = Board([['e'; 10]; 10]);
-        for &length in Self::SHIPS.iter() {
-            loop {
-                let dir = rand::thread_rng().gen_bool(0.5);
-                let x = rand::thread_rng().gen_range(0..(if dir { 10 } else { 11 - length }));
-                let y = rand::thread_rng().gen_range(0..(if dir { 11 - length } else { 10 }));
-                if board.is_overlapping(x, y, length, dir) {
+    fn longest_first(fleet: &[usize]) -> Vec<usize> {
+        let mut ships: Vec<usize> = (0..fleet.len()).collect();
+        ships.sort_by(|&a, &b| fleet[b].cmp(&fleet[a]));
+        ships
+    }
+
+    /// Places `ships` in order, each at the first free position from `from` in reading order.
+    fn place_from(
+        &mut self,
+        ships: &[usize],
+        fleet: &[usize],
+        from: usize,
+        steps: &mut usize,
+    ) -> bool {
+        let Some((&id, rest)) = ships.split_first() else {
+            return true;
+        };
+        let (h, w) = (self.height(), self.width());
+        let length = fleet[id];
+        for start in from..h * w {
+            let (x, y) = (start / w, start % w);
+            for dir in [true, false] {
+                if *steps == 0 {
+                    return false;
+                }
+                *steps -= 1;
+                let fits = if dir {
+                    y + length <= w
+                } else {
+                    x + length <= h
+                };
+                if !fits || self.is_overlapping(x as i32, y as i32, length as i32, dir) {
                     continue;
                 }
-                for i in 0..length {
-                    let (tx, ty) = if dir { (x, y + i) } else { (x + i, y) };
-                    board[tx as usize][ty as usize] = 's';
+                let cells = (0..length).map(|i| if dir { (x, y + i) } else { (x + i, y) });
+                for (tx, ty) in cells.clone() {
+                    self[tx][ty] = Cell::Ship(id as u8);
+                }
+                // ships of the same length are interchangeable, so each goes after the one before
+                let next = match rest.first() {
+                    Some(&next) if fleet[next] == length => start,
+                    _ => 0,
+                };
+                if self.place_from(rest, fleet, next, steps) {
+                    return true;
+                }
+                for (tx, ty) in cells {
+                    self[tx][ty] = Cell::Empty;
                 }
-                break;
             }
         }
-        board
+        false
+    }
+
+    fn place_random(&mut self, id: u8, length: i32) -> bool {
+        let (h, w) = (self.height() as i32, self.width() as i32);
+        for _ in 0..Self::PLACEMENT_ATTEMPTS {
+            let dir = rand::thread_rng().gen_bool(0.5);
+            let (max_x, max_y) = if dir {
+                (h, w + 1 - length)
+            } else {
+                (h + 1 - length, w)
+            };
+            if max_x <= 0 || max_y <= 0 {
+                continue;
+            }
+            let x = rand::thread_rng().gen_range(0..max_x);
+            let y = rand::thread_rng().gen_range(0..max_y);
+            if self.is_overlapping(x, y, length, dir) {
+                continue;
+            }
+            for i in 0..length {
+                let (tx, ty) = if dir { (x, y + i) } else { (x + i, y) };
+                self[tx as usize][ty as usize] = Cell::Ship(id);
+            }
+            return true;
+        }
+        false
     }
 
     fn is_overlapping(&self, x: i32, y: i32, length: i32, dir: bool) -> bool {
         for i in -1..2 {
             for j in -1..=length {
                 let (tx, ty) = if dir { (x + i, y + j) } else { (x + j, y + i) };
-                if !(0..10).contains(&tx) || !(0..10).contains(&ty) {
+                if tx < 0 || ty < 0 || !self.contains((tx as usize, ty as usize)) {
                     continue;
                 }
-                if self[tx as usize][ty as usize] != 'e' {
+                if self[tx as usize][ty as usize] != Cell::Empty {
                     return true;
                 }
             }
@@ -81,50 +318,176 @@ impl Board {
         false
     }
 
-    pub fn has_sunk(&self, (i, j): (usize, usize)) -> Option<[(usize, usize); 2]> {
-        let mut queue = vec![(i, j)];
-        let mut visited = vec![vec![false; 10]; 10];
+    /// Cells of every ship drawn on a layout, found as orthogonally connected groups.
+    fn components(layout: &Layout) -> Vec<Vec<(usize, usize)>> {
+        let (height, width) = (layout.len(), layout[0].len());
+        let mut visited = vec![vec![false; width]; height];
+        let mut ships = vec![];
+        for i in 0..height {
+            for j in 0..width {
+                if visited[i][j] || layout[i][j] != 's' {
+                    continue;
+                }
+                let mut queue = vec![(i, j)];
+                let mut cells = vec![];
+                visited[i][j] = true;
+                while let Some((x, y)) = queue.pop() {
+                    cells.push((x, y));
+                    for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                        let (tx, ty) = ((x as i32 + dx) as usize, (y as i32 + dy) as usize);
+                        if tx < height && ty < width && !visited[tx][ty] && layout[tx][ty] == 's' {
+                            visited[tx][ty] = true;
+                            queue.push((tx, ty));
+                        }
+                    }
+                }
+                ships.push(cells);
+            }
+        }
+        ships
+    }
+
+    /// Checks a freshly placed layout against the room's dimensions and fleet,
+    /// and numbers its ships after the fleet.
+    pub fn from_layout(
+        layout: &Layout,
+        height: usize,
+        width: usize,
+        fleet: &[usize],
+    ) -> Result<Self, Invalid> {
+        if layout.len() != height || layout.iter().any(|row| row.len() != width) {
+            return Err(Invalid::Size { height, width });
+        }
+        for (i, row) in layout.iter().enumerate() {
+            if let Some((j, &cell)) = row
+                .iter()
+                .enumerate()
+                .find(|(_, c)| !matches!(c, 'e' | 's'))
+            {
+                return Err(Invalid::Cell { at: (i, j), cell });
+            }
+        }
+        let ships = Self::components(layout);
+        for cells in ships.iter() {
+            let (x, y) = cells[0];
+            if !cells.iter().all(|&(i, _)| i == x) && !cells.iter().all(|&(_, j)| j == y) {
+                return Err(Invalid::Bent { at: (x, y) });
+            }
+            // straight ships can only meet another ship diagonally
+            for &(i, j) in cells.iter() {
+                for (dx, dy) in [(-1, -1), (1, 1), (1, -1), (-1, 1)].iter() {
+                    let (tx, ty) = ((i as i32 + dx) as usize, (j as i32 + dy) as usize);
+                    if tx < height && ty < width && layout[tx][ty] == 's' {
+                        return Err(Invalid::Touching { at: (i, j) });
+                    }
+                }
+            }
+        }
+        let mut expected = fleet.to_vec();
+        expected.sort_unstable_by(|a, b| b.cmp(a));
+        let mut found: Vec<usize> = ships.iter().map(|cells| cells.len()).collect();
+        found.sort_unstable_by(|a, b| b.cmp(a));
+        if found != expected {
+            return Err(Invalid::Fleet { expected, found });
+        }
+
+        let mut board = Board::new(height, width);
+        let mut taken = vec![false; fleet.len()];
+        for cells in ships.iter() {
+            let id = (0..fleet.len())
+                .find(|&id| !taken[id] && fleet[id] == cells.len())
+                .unwrap();
+            taken[id] = true;
+            for &(i, j) in cells.iter() {
+                board[i][j] = Cell::Ship(id as u8);
+            }
+        }
+        Ok(board)
+    }
+
+    /// Fires at a cell, returns whether a ship was hit or `None` if the cell cannot be shot at.
+    pub fn fire(&mut self, (i, j): (usize, usize)) -> Option<bool> {
+        if !self.contains((i, j)) {
+            return None;
+        }
+        match self[i][j] {
+            Cell::Ship(id) => {
+                self[i][j] = Cell::Hit(id);
+                Some(true)
+            }
+            Cell::Empty => {
+                self[i][j] = Cell::Miss;
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of ships with at least one cell that has not been hit.
+    pub fn ships_afloat(&self) -> usize {
+        let mut afloat: Vec<u8> = self
+            .iter()
+            .flatten()
+            .filter_map(|cell| match cell {
+                Cell::Ship(id) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        afloat.sort_unstable();
+        afloat.dedup();
+        afloat.len()
+    }
+
+    /// Number of cells that have not been shot at yet.
+    pub fn unexplored(&self) -> usize {
+        self.iter()
+            .flatten()
+            .filter(|cell| matches!(cell, Cell::Empty | Cell::Ship(_)))
+            .count()
+    }
+
+    /// Cells of the ship with the given id.
+    fn ship(&self, id: u8) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.height())
+            .flat_map(move |i| (0..self.width()).map(move |j| (i, j)))
+            .filter(move |&(i, j)| self[i][j].ship() == Some(id))
+    }
+
+    pub fn has_sunk(&self, (i, j): (usize, usize), fleet: &[usize]) -> Option<Sunk> {
+        let id = self[i][j].ship()?;
         let mut bounds = [(i, j), (i, j)];
-        visited[i][j] = true;
-        while let Some((x, y)) = queue.pop() {
-            if self[x][y] == 's' {
+        for (x, y) in self.ship(id) {
+            if let Cell::Ship(_) = self[x][y] {
                 return None;
             }
             bounds[0].0 = bounds[0].0.min(x);
             bounds[0].1 = bounds[0].1.min(y);
             bounds[1].0 = bounds[1].0.max(x);
             bounds[1].1 = bounds[1].1.max(y);
-            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
-                let (tx, ty) = ((x as i32 + dx) as usize, (y as i32 + dy) as usize);
-                if (0..10).contains(&tx)
-                    && (0..10).contains(&ty)
-                    && !visited[tx][ty]
-                    && matches!(self[tx][ty], 'h' | 's')
-                {
-                    visited[tx][ty] = true;
-                    queue.push((tx, ty));
-                }
-            }
         }
-        Some(bounds)
+        Some(Sunk {
+            name: ship_name(fleet, id as usize),
+            length: fleet[id as usize],
+            bounds,
+        })
     }
 
-    pub fn mark_redundant(mut self) -> Self {
-        for i in 0..10 {
-            for j in 0..10 {
-                if self[i][j] == 'h' {
+    /// Marks cells that cannot hold a ship anymore as missed, for the client to draw.
+    pub fn mark_redundant(mut self, fleet: &[usize]) -> Self {
+        for i in 0..self.height() {
+            for j in 0..self.width() {
+                if let Cell::Hit(_) = self[i][j] {
                     for (dx, dy) in [(-1, -1), (1, 1), (1, -1), (-1, 1)].iter() {
                         let (tx, ty) = ((i as i32 + dx) as usize, (j as i32 + dy) as usize);
-                        if (0..10).contains(&tx) && (0..10).contains(&ty) {
-                            self[tx][ty] = 'm';
+                        if self.contains((tx, ty)) && self[tx][ty] == Cell::Empty {
+                            self[tx][ty] = Cell::Miss;
                         }
                     }
-                    if self.has_sunk((i, j)).is_some() {
+                    if self.has_sunk((i, j), fleet).is_some() {
                         for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
                             let (tx, ty) = ((i as i32 + dx) as usize, (j as i32 + dy) as usize);
-                            if (0..10).contains(&tx) && (0..10).contains(&ty) && self[tx][ty] == 'e'
-                            {
-                                self[tx][ty] = 'm';
+                            if self.contains((tx, ty)) && self[tx][ty] == Cell::Empty {
+                                self[tx][ty] = Cell::Miss;
                             }
                         }
                     }
@@ -134,18 +497,147 @@ impl Board {
         self
     }
 
-    pub fn is_game_over(&self) -> bool {
-        !self.iter().any(|row| row.iter().any(|&cell| cell == 's'))
+    /// The game is over once every cell of the fleet has been hit.
+    pub fn is_game_over(&self, fleet: &[usize]) -> bool {
+        let hits = self
+            .iter()
+            .flatten()
+            .filter(|cell| matches!(cell, Cell::Hit(_)))
+            .count();
+        hits >= fleet.iter().sum()
     }
-
-    // fn validate_syntax(&self) -> bool {
-    //     self
-    //         .iter()
-    //         .all(|row| row.iter().all(|cell| matches!(cell, 'e' | 'h' | 'm' | 's')))
-    // }
 }
 
-// pub async fn create_board_route(board: Json<Board>) -> Json<String> {
-//     let board = Board::from_json(board).await;
-//     Json(format!("{:?}", board))
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn layout(board: &Board) -> Layout {
+        board
+            .view(true)
+            .iter()
+            .map(|row| row.chars().collect())
+            .collect()
+    }
+
+    fn parse(rows: &[&str]) -> Layout {
+        rows.iter()
+            .map(|row| {
+                row.chars()
+                    .map(|c| if c == '.' { 'e' } else { c })
+                    .collect()
+            })
+            .collect()
+    }
+
+    const FLEET: [usize; 3] = [3, 2, 2];
+
+    fn check(rows: &[&str]) -> Result<Board, Invalid> {
+        Board::from_layout(&parse(rows), 5, 6, &FLEET)
+    }
+
+    #[test]
+    fn from_layout_numbers_ships_after_the_fleet() {
+        let board = check(&["sss...", "......", "ss..ss", "......", "......"]).unwrap();
+        assert_eq!(board[0][0], Cell::Ship(0));
+        assert_eq!(board[2][0], Cell::Ship(1));
+        assert_eq!(board[2][4], Cell::Ship(2));
+        assert!(check(&["s.....", "s.ss..", "s.....", "....s.", "....s."]).is_ok());
+    }
+
+    #[test]
+    fn from_layout_rejects_touching_ships() {
+        let touching = check(&["sss...", "...ss.", "......", "ss....", "......"]);
+        assert!(matches!(touching, Err(Invalid::Touching { .. })));
+        // side by side reads as one bent ship
+        let adjacent = check(&["sss...", "ss....", "......", "ss....", "......"]);
+        assert!(matches!(adjacent, Err(Invalid::Bent { .. })));
+    }
+
+    #[test]
+    fn from_layout_rejects_bent_ships() {
+        let bent = check(&["ss....", ".s....", "......", "ss..ss", "......"]);
+        assert!(matches!(bent, Err(Invalid::Bent { at: (0, 0) })));
+    }
+
+    #[test]
+    fn from_layout_rejects_extra_and_missing_ships() {
+        let extra = check(&["sss...", "......", "ss..ss", "......", "s....."]);
+        assert!(matches!(
+            extra,
+            Err(Invalid::Fleet { expected, found }) if expected == [3, 2, 2] && found == [3, 2, 2, 1]
+        ));
+        let missing = check(&["sss...", "......", "ss....", "......", "......"]);
+        assert!(matches!(
+            missing,
+            Err(Invalid::Fleet { found, .. }) if found == [3, 2]
+        ));
+        let wrong_length = check(&["ssss..", "......", "ss..ss", "......", "......"]);
+        assert!(matches!(wrong_length, Err(Invalid::Fleet { .. })));
+    }
+
+    #[test]
+    fn from_layout_rejects_unknown_cells_and_sizes() {
+        let hit = check(&["sss...", "......", "ss..ss", "..h...", "......"]);
+        assert!(matches!(
+            hit,
+            Err(Invalid::Cell {
+                at: (3, 2),
+                cell: 'h'
+            })
+        ));
+        let letter = check(&["sss...", "......", "ss..ss", "......", ".....x"]);
+        assert!(matches!(
+            letter,
+            Err(Invalid::Cell {
+                at: (4, 5),
+                cell: 'x'
+            })
+        ));
+        let short = check(&["sss...", "......", "ss..ss", "......"]);
+        assert!(matches!(
+            short,
+            Err(Invalid::Size {
+                height: 5,
+                width: 6
+            })
+        ));
+        let narrow = check(&["sss..", ".....", "ss.ss", ".....", "....."]);
+        assert!(matches!(narrow, Err(Invalid::Size { .. })));
+    }
+
+    #[test]
+    fn fit_places_tight_fleets() {
+        // only the layouts with the carriers on alternate rows or columns fit
+        let board = Board::fit(5, 5, &[5, 5, 5]).unwrap();
+        assert!(Board::from_layout(&layout(&board), 5, 5, &[5, 5, 5]).is_ok());
+        let board = Board::fit(10, 10, &CLASSIC_FLEET).unwrap();
+        assert!(Board::from_layout(&layout(&board), 10, 10, &CLASSIC_FLEET).is_ok());
+    }
+
+    #[test]
+    fn fit_rejects_fleets_that_do_not_fit() {
+        assert!(Board::fit(5, 5, &[6]).is_none());
+        assert!(Board::fit(5, 5, &[5, 5, 5, 1]).is_none());
+        assert!(Board::fit(5, 5, &[2; 9]).is_none());
+    }
+
+    #[test]
+    fn randomize_places_tight_fleets() {
+        for _ in 0..20 {
+            assert!(Board::randomize(5, 5, &[5, 5, 5]).is_some());
+        }
+    }
+
+    #[test]
+    fn shuffled_layouts_stay_valid_and_vary() {
+        let layouts: HashSet<Layout> = (0..50)
+            .map(|_| layout(&Board::fit(10, 10, &CLASSIC_FLEET).unwrap().shuffled()))
+            .collect();
+        assert!(layouts.len() > 1);
+        for shuffled in &layouts {
+            assert!(Board::from_layout(shuffled, 10, 10, &CLASSIC_FLEET).is_ok());
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use serde::Serialize;
+
+/// How likely each cell is to hold a ship, counted as the number of placements of the
+/// remaining ships that cover it.
+#[derive(Debug, Serialize)]
+pub struct Heatmap {
+    pub heat: Vec<Vec<usize>>,
+    /// Hottest cell that has not been shot at, `None` once every cell has been.
+    pub best: Option<(usize, usize)>,
+}
+
+fn cells(view: &[String]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..view.len()).flat_map(move |i| (0..view[i].len()).map(move |j| (i, j)))
+}
+
+fn at(view: &[String], (i, j): (usize, usize)) -> u8 {
+    view[i].as_bytes()[j]
+}
+
+/// Cells of a view, as returned by `get_game_state`, that have not been shot at.
+pub fn unexplored(view: &[String]) -> Vec<(usize, usize)> {
+    cells(view).filter(|&cell| at(view, cell) == b'e').collect()
+}
+
+pub fn neighbours(
+    view: &[String],
+    (i, j): (usize, usize),
+) -> impl Iterator<Item = (usize, usize)> + '_ {
+    [(-1, 0), (1, 0), (0, -1), (0, 1)]
+        .into_iter()
+        .map(move |(dx, dy)| ((i as i32 + dx) as usize, (j as i32 + dy) as usize))
+        .filter(|&(x, y)| x < view.len() && y < view[x].len())
+}
+
+/// Hits grouped by ship, with whether the ship is sunk.
+/// Cells next to a sunk ship are drawn as misses, so a ship still afloat is the only one
+/// with unexplored cells next to its hits.
+pub fn ships(view: &[String]) -> Vec<(Vec<(usize, usize)>, bool)> {
+    let mut visited = vec![vec![false; view.first().map_or(0, |row| row.len())]; view.len()];
+    let mut ships = vec![];
+    for (i, j) in cells(view) {
+        if at(view, (i, j)) != b'h' || visited[i][j] {
+            continue;
+        }
+        visited[i][j] = true;
+        let (mut ship, mut stack, mut sunk) = (vec![], vec![(i, j)], true);
+        while let Some(cell) = stack.pop() {
+            ship.push(cell);
+            for (x, y) in neighbours(view, cell) {
+                match at(view, (x, y)) {
+                    b'h' if !visited[x][y] => {
+                        visited[x][y] = true;
+                        stack.push((x, y));
+                    }
+                    b'e' => sunk = false,
+                    _ => {}
+                }
+            }
+        }
+        ships.push((ship, sunk));
+    }
+    ships
+}
+
+/// Lengths of the ships of the fleet that have not been sunk yet.
+pub fn remaining(view: &[String], fleet: &[usize]) -> Vec<usize> {
+    let mut fleet = fleet.to_vec();
+    for (ship, _) in ships(view).into_iter().filter(|(_, sunk)| *sunk) {
+        if let Some(k) = fleet.iter().position(|&length| length == ship.len()) {
+            fleet.swap_remove(k);
+        }
+    }
+    fleet
+}
+
+/// Counts the placements of the remaining ships over the cells of the view that could still
+/// hold them. While a ship is wounded only placements through its hits are counted, weighted
+/// by how many of them they cover.
+pub fn heatmap(view: &[String], remaining: &[usize]) -> Heatmap {
+    let (height, width) = (view.len(), view.first().map_or(0, |row| row.len()));
+    let mut wounded = vec![vec![false; width]; height];
+    for (ship, _) in ships(view).into_iter().filter(|(_, sunk)| !sunk) {
+        for (i, j) in ship {
+            wounded[i][j] = true;
+        }
+    }
+    let hunting = !wounded.iter().flatten().any(|&hit| hit);
+    let open = |cell: (usize, usize)| at(view, cell) == b'e' || wounded[cell.0][cell.1];
+    let is_hit = |(i, j): (usize, usize)| i < height && j < width && at(view, (i, j)) == b'h';
+
+    // ships of the same length have the same placements
+    let mut lengths = remaining.to_vec();
+    lengths.sort_unstable();
+    let mut heat = vec![vec![0; width]; height];
+    for (k, &length) in lengths.iter().enumerate() {
+        if length == 0 || lengths.get(k + 1) == Some(&length) {
+            continue;
+        }
+        let count = lengths.iter().filter(|&&l| l == length).count();
+        let directions: &[(usize, usize)] = if length == 1 {
+            &[(0, 1)]
+        } else {
+            &[(0, 1), (1, 0)]
+        };
+        for &(dx, dy) in directions {
+            for (i, j) in cells(view) {
+                let (end_i, end_j) = (i + (length - 1) * dx, j + (length - 1) * dy);
+                if end_i >= height || end_j >= width {
+                    continue;
+                }
+                let placement = (0..length).map(|n| (i + n * dx, j + n * dy));
+                if !placement.clone().all(open) {
+                    continue;
+                }
+                // ships never touch, a hit next to the placement would be part of it
+                let (before, after) = (
+                    (i.wrapping_sub(dx), j.wrapping_sub(dy)),
+                    (end_i + dx, end_j + dy),
+                );
+                if is_hit(before)
+                    || is_hit(after)
+                    || placement.clone().any(|(x, y)| {
+                        is_hit((x.wrapping_sub(dy), y.wrapping_sub(dx))) || is_hit((x + dy, y + dx))
+                    })
+                {
+                    continue;
+                }
+                let hits = placement.clone().filter(|&(x, y)| wounded[x][y]).count();
+                if !hunting && hits == 0 {
+                    continue;
+                }
+                for (x, y) in placement.filter(|&cell| at(view, cell) == b'e') {
+                    heat[x][y] += count * (1 + hits);
+                }
+            }
+        }
+    }
+
+    let best = unexplored(view)
+        .into_iter()
+        .rev()
+        .max_by_key(|&(i, j)| heat[i][j]);
+    Heatmap { heat, best }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(rows: &[&str]) -> Vec<String> {
+        rows.iter().map(|row| row.to_string()).collect()
+    }
+
+    #[test]
+    fn heatmap_counts_every_placement_while_hunting() {
+        let heatmap = heatmap(&view(&["eee", "eee", "eee"]), &[2]);
+        // two placements per row and per column, the middle cells are in both of theirs
+        assert_eq!(heatmap.heat, [[2, 3, 2], [3, 4, 3], [2, 3, 2]]);
+        assert_eq!(heatmap.best, Some((1, 1)));
+    }
+
+    #[test]
+    fn heatmap_skips_placements_through_misses() {
+        let heatmap = heatmap(&view(&["eee", "eme", "eee"]), &[2]);
+        assert_eq!(heatmap.heat, [[2, 2, 2], [2, 0, 2], [2, 2, 2]]);
+        // ties go to the first cell in reading order
+        assert_eq!(heatmap.best, Some((0, 0)));
+    }
+
+    #[test]
+    fn heatmap_only_counts_placements_through_a_wounded_ship() {
+        let heatmap = heatmap(&view(&["eee", "ehe", "eee"]), &[2, 3]);
+        // the ship of 3 once each way through the hit, the ship of 2 on either side of it, each
+        // counted twice for covering one hit
+        assert_eq!(heatmap.heat, [[0, 4, 0], [4, 0, 4], [0, 4, 0]]);
+        assert_eq!(heatmap.best, Some((0, 1)));
+    }
+
+    #[test]
+    fn sunk_ships_leave_the_fleet() {
+        let sunk = view(&["mmmm", "mhhm", "mmmm", "eeee"]);
+        assert_eq!(ships(&sunk), [(vec![(1, 1), (1, 2)], true)]);
+        assert_eq!(remaining(&sunk, &[3, 2]), [3]);
+        let afloat = view(&["mmme", "mhhe", "mmmm", "eeee"]);
+        assert_eq!(remaining(&afloat, &[3, 2]), [3, 2]);
+    }
+
+    #[test]
+    fn heatmap_covers_the_largest_board_and_fleet() {
+        let board = view(&["e".repeat(20).as_str(); 20]);
+        let fleet = [5, 4, 4, 3, 3, 3, 2, 2, 2, 2, 1, 1, 1, 1, 1];
+        let map = heatmap(&board, &fleet);
+        assert_eq!(map.heat.len(), 20);
+        assert!(map.heat.iter().all(|row| row.len() == 20));
+        assert!(map.best.is_some());
+    }
+}
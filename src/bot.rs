@@ -0,0 +1,195 @@
+use rand::seq::SliceRandom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::board::density::{heatmap, neighbours, remaining, ships, unexplored, Heatmap};
+use crate::game::{get_game_state, get_rules, Error, Result};
+
+/// The computer takes the seat of player 2 with the room code appended to this,
+/// socket ids never contain '~'.
+const BOT_ID_PREFIX: &str = "~~~~computer";
+
+/// How the computer picks its shots.
+#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[sqlx(type_name = "DIFFICULTY", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    /// Shoots anywhere that has not been shot at.
+    Random,
+    /// Shoots on a checkerboard until it hits, then finishes off the ship.
+    Hunt,
+    /// Shoots where most placements of the remaining ships overlap.
+    Density,
+}
+
+pub fn bot_id(code: &str) -> String {
+    format!("{BOT_ID_PREFIX}{code}")
+}
+
+pub fn is_bot(id: &str) -> bool {
+    id.starts_with(BOT_ID_PREFIX)
+}
+
+/// Picks the computer's next shot at its opponent in the room, it only knows what a player would.
+pub async fn next_shot(
+    code: &str,
+    difficulty: Difficulty,
+    pool: &sqlx::PgPool,
+) -> Result<(usize, usize)> {
+    let opponent = get_game_state(&bot_id(code), code, pool).await?.opponent;
+    let fleet = get_rules(code, pool).await?.fleet;
+    match difficulty {
+        Difficulty::Random => random(&opponent),
+        Difficulty::Hunt => hunt(&opponent, &fleet),
+        Difficulty::Density => density(&opponent, &fleet),
+    }
+    .ok_or(Error::InvalidMove)
+}
+
+fn random(view: &[String]) -> Option<(usize, usize)> {
+    unexplored(view).choose(&mut rand::thread_rng()).copied()
+}
+
+fn hunt(view: &[String], fleet: &[usize]) -> Option<(usize, usize)> {
+    let mut rng = rand::thread_rng();
+    if let Some((ship, _)) = ships(view).into_iter().find(|(_, sunk)| !sunk) {
+        // once two cells are hit the direction is known and only the ends are left
+        let in_line = |(i, j): (usize, usize)| {
+            ship.len() == 1
+                || ship.iter().all(|&(x, _)| x == i)
+                || ship.iter().all(|&(_, y)| y == j)
+        };
+        let targets: Vec<_> = ship
+            .iter()
+            .flat_map(|&at| neighbours(view, at))
+            .filter(|&(i, j)| view[i].as_bytes()[j] == b'e' && in_line((i, j)))
+            .collect();
+        return targets.choose(&mut rng).copied();
+    }
+    // every placement of the smallest ship left covers a cell of this checkerboard
+    let smallest = remaining(view, fleet).into_iter().min().unwrap_or(1);
+    let cells = unexplored(view);
+    let checkerboard: Vec<_> = cells
+        .iter()
+        .filter(|&&(i, j)| (i + j) % smallest == 0)
+        .copied()
+        .collect();
+    checkerboard
+        .choose(&mut rng)
+        .or(cells.choose(&mut rng))
+        .copied()
+}
+
+fn density(view: &[String], fleet: &[usize]) -> Option<(usize, usize)> {
+    let Heatmap { heat, best } = heatmap(view, &remaining(view, fleet));
+    // break ties at random so the computer is harder to read
+    let hottest = best.map(|(i, j)| heat[i][j])?;
+    let best: Vec<_> = unexplored(view)
+        .into_iter()
+        .filter(|&(i, j)| heat[i][j] == hottest)
+        .collect();
+    best.choose(&mut rand::thread_rng()).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use std::collections::HashSet;
+
+    /// Shots asked of each strategy, enough to see every cell it may pick.
+    const SHOTS: usize = 200;
+
+    fn view(rows: &[&str]) -> Vec<String> {
+        rows.iter().map(|row| row.to_string()).collect()
+    }
+
+    /// What the opponent sees of the fleet after the shots, as the server draws it.
+    fn seen(rows: &[&str], fleet: &[usize], shots: &[(usize, usize)]) -> Vec<String> {
+        let layout: Vec<Vec<char>> = rows.iter().map(|row| row.chars().collect()).collect();
+        let mut board = Board::from_layout(&layout, rows.len(), rows[0].len(), fleet).unwrap();
+        for &at in shots {
+            board.fire(at).unwrap();
+        }
+        board.mark_redundant(fleet).view(false)
+    }
+
+    fn shots(mut pick: impl FnMut() -> Option<(usize, usize)>) -> HashSet<(usize, usize)> {
+        (0..SHOTS).map(|_| pick().unwrap()).collect()
+    }
+
+    fn unexplored(view: &[String], (i, j): (usize, usize)) -> bool {
+        view[i].as_bytes()[j] == b'e'
+    }
+
+    #[test]
+    fn random_shoots_only_unexplored_cells() {
+        let view = view(&["mmm", "mem", "hme"]);
+        assert_eq!(shots(|| random(&view)), HashSet::from([(1, 1), (2, 2)]));
+        assert_eq!(random(&["mh".into(), "hm".into()]), None);
+    }
+
+    #[test]
+    fn hunt_tries_every_side_of_a_single_hit() {
+        let view = view(&["eeeee", "eeeee", "eehee", "eeeee", "eeeee"]);
+        assert_eq!(
+            shots(|| hunt(&view, &[3])),
+            HashSet::from([(1, 2), (3, 2), (2, 1), (2, 3)])
+        );
+    }
+
+    #[test]
+    fn hunt_follows_the_line_of_a_wounded_ship() {
+        let open = view(&["eeeeee", "eeeeee", "eehhee", "eeeeee"]);
+        assert_eq!(shots(|| hunt(&open, &[4])), HashSet::from([(2, 1), (2, 4)]));
+        let missed_one_end = view(&["eeeeee", "eeeeee", "emhhee", "eeeeee"]);
+        assert_eq!(
+            shots(|| hunt(&missed_one_end, &[4])),
+            HashSet::from([(2, 4)])
+        );
+    }
+
+    #[test]
+    fn hunt_never_targets_explored_cells() {
+        let mostly_missed = view(&["mmmme", "memmm", "mmmmm", "emmem"]);
+        for at in shots(|| hunt(&mostly_missed, &[2])) {
+            assert!(unexplored(&mostly_missed, at), "{at:?}");
+        }
+        // off the checkerboard once only those are left
+        assert_eq!(hunt(&view(&["mem", "mmm"]), &[2]), Some((0, 1)));
+    }
+
+    #[test]
+    fn bots_leave_sunk_ships_alone() {
+        let rows = ["sseee", "eeeee", "eeeee", "eesss", "eeeee"];
+        // the destroyer sunk in the corner, nothing else hit
+        let view = seen(&rows, &[3, 2], &[(0, 0), (0, 1)]);
+        let next_to_sunk = |(i, j): (usize, usize)| i <= 1 && j <= 2;
+        for pick in [
+            random,
+            |view: &[String]| hunt(view, &[3, 2]),
+            |view: &[String]| density(view, &[3, 2]),
+        ] {
+            for at in shots(|| pick(&view)) {
+                assert!(unexplored(&view, at) && !next_to_sunk(at), "{at:?}");
+            }
+        }
+        // and with the cruiser wounded, only it is followed
+        let view = seen(&rows, &[3, 2], &[(0, 0), (0, 1), (3, 3)]);
+        let around = HashSet::from([(2, 3), (4, 3), (3, 2), (3, 4)]);
+        assert_eq!(shots(|| hunt(&view, &[3, 2])), around);
+        assert!(shots(|| density(&view, &[3, 2])).is_subset(&around));
+    }
+
+    #[test]
+    fn density_shoots_the_hottest_cells() {
+        // both placements of a destroyer in a row of three cover the middle
+        assert_eq!(density(&view(&["eee"]), &[2]), Some((0, 1)));
+        let view = view(&["eeeee", "eeeee", "eehee", "eeeee", "eeeee"]);
+        assert_eq!(
+            shots(|| density(&view, &[2])),
+            HashSet::from([(1, 2), (3, 2), (2, 1), (2, 3)])
+        );
+        assert_eq!(density(&["mh".into(), "hm".into()], &[2]), None);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::game::{Error, Result};
+
+/// Longest message accepted, in characters.
+pub const MAX_MESSAGE_LENGTH: usize = 200;
+/// Messages a socket may send within `RATE_WINDOW`.
+pub const RATE_LIMIT: usize = 5;
+pub const RATE_WINDOW: Duration = Duration::from_secs(10);
+/// Messages sent back to a returning player.
+pub const RECENT_MESSAGES: i64 = 50;
+
+/// Words masked by the default filter.
+const PROFANITY: &[&str] = &[
+    "arse",
+    "asshole",
+    "bastard",
+    "bitch",
+    "bollocks",
+    "cock",
+    "crap",
+    "cunt",
+    "damn",
+    "dick",
+    "fuck",
+    "fucker",
+    "fucking",
+    "motherfucker",
+    "piss",
+    "prick",
+    "shit",
+    "slut",
+    "twat",
+    "wanker",
+    "whore",
+];
+
+#[derive(Debug, Serialize)]
+pub struct Message {
+    pub from: String,
+    pub text: String,
+    /// Milliseconds since the epoch.
+    pub time: i64,
+}
+
+/// Moderates chat before it is stored and sent on, returning the text to send, as is or
+/// changed, or an error to drop the message.
+pub trait ChatFilter: Send + Sync {
+    fn filter(&self, text: &str) -> Result<String>;
+}
+
+/// Masks whole words of a list with asterisks, ignoring case.
+pub struct ProfanityList {
+    words: Vec<String>,
+}
+
+impl ProfanityList {
+    pub fn new<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> Self {
+        let words = words
+            .into_iter()
+            .map(|word| word.as_ref().to_lowercase())
+            .collect();
+        Self { words }
+    }
+}
+
+impl Default for ProfanityList {
+    fn default() -> Self {
+        Self::new(PROFANITY)
+    }
+}
+
+impl ChatFilter for ProfanityList {
+    fn filter(&self, text: &str) -> Result<String> {
+        let mut filtered = String::with_capacity(text.len());
+        let mut word = String::new();
+        // a trailing separator flushes the last word
+        for c in text.chars().chain(Some(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if self.words.contains(&word.to_lowercase()) {
+                filtered.extend(word.chars().map(|_| '*'));
+            } else {
+                filtered.push_str(&word);
+            }
+            word.clear();
+            filtered.push(c);
+        }
+        filtered.pop();
+        Ok(filtered)
+    }
+}
+
+/// Chat shared by every socket, holding the filter and when each socket last sent messages.
+#[derive(Clone)]
+pub struct Chat {
+    filter: Arc<dyn ChatFilter>,
+    sent: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+}
+
+impl Chat {
+    pub fn new(filter: impl ChatFilter + 'static) -> Self {
+        Self {
+            filter: Arc::new(filter),
+            sent: Default::default(),
+        }
+    }
+
+    /// Checks a message from `sender` against the limits and the filter, returning what to send.
+    pub fn check(&self, sender: &str, text: &str) -> Result<String> {
+        let text = text.trim();
+        if text.is_empty() || text.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(Error::InvalidMessage);
+        }
+        let now = Instant::now();
+        {
+            let mut sent = self.sent.lock().unwrap();
+            let times = sent.entry(sender.to_string()).or_default();
+            while times
+                .front()
+                .is_some_and(|&time| now.duration_since(time) >= RATE_WINDOW)
+            {
+                times.pop_front();
+            }
+            if times.len() >= RATE_LIMIT {
+                return Err(Error::RateLimited);
+            }
+            times.push_back(now);
+        }
+        self.filter.filter(text)
+    }
+
+    /// Drops the rate limit state of a socket that has gone.
+    pub fn forget(&self, sender: &str) {
+        self.sent.lock().unwrap().remove(sender);
+    }
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Self::new(ProfanityList::default())
+    }
+}
+
+pub async fn save_message(
+    room: &str,
+    sender: &str,
+    text: &str,
+    pool: &sqlx::PgPool,
+) -> Result<Message> {
+    let time = sqlx::query_scalar!(
+        r#"INSERT INTO messages (room_code, sender, text) VALUES ($1, $2, $3) RETURNING (EXTRACT(EPOCH FROM time) * 1000)::BIGINT AS "time!""#,
+        room,
+        sender,
+        text
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(Message {
+        from: sender.to_string(),
+        text: text.to_string(),
+        time,
+    })
+}
+
+/// The last `RECENT_MESSAGES` messages of the room, oldest first.
+pub async fn recent_messages(room: &str, pool: &sqlx::PgPool) -> Result<Vec<Message>> {
+    let mut messages = sqlx::query_as!(
+        Message,
+        r#"SELECT sender AS "from", text, (EXTRACT(EPOCH FROM time) * 1000)::BIGINT AS "time!" FROM messages WHERE room_code = $1 ORDER BY id DESC LIMIT $2"#,
+        room,
+        RECENT_MESSAGES
+    )
+    .fetch_all(pool)
+    .await?;
+    messages.reverse();
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(text: &str) -> String {
+        ProfanityList::default().filter(text).unwrap()
+    }
+
+    #[test]
+    fn masks_listed_words_whatever_their_case() {
+        assert_eq!(filter("well shit"), "well ****");
+        assert_eq!(filter("DAMN it, Damn"), "**** it, ****");
+    }
+
+    #[test]
+    fn keeps_punctuation_and_spacing_around_masked_words() {
+        assert_eq!(filter("crap!"), "****!");
+        assert_eq!(filter("  (twat)  "), "  (****)  ");
+        assert_eq!(filter("piss-poor"), "****-poor");
+    }
+
+    #[test]
+    fn leaves_words_that_only_contain_a_listed_word() {
+        assert_eq!(filter("scrap the cocktail"), "scrap the cocktail");
+        assert_eq!(filter("Scunthorpe"), "Scunthorpe");
+    }
+
+    #[test]
+    fn leaves_clean_text_as_is() {
+        assert_eq!(filter("good game, well played"), "good game, well played");
+        assert_eq!(filter("Schiffe versenkt! 🚢"), "Schiffe versenkt! 🚢");
+        assert_eq!(filter(""), "");
+    }
+
+    #[test]
+    fn masks_a_custom_list_only() {
+        let list = ProfanityList::new(["Kraken"]);
+        assert_eq!(
+            list.filter("the kraken sank my shit").unwrap(),
+            "the ****** sank my shit"
+        );
+    }
+
+    #[test]
+    fn check_trims_and_bounds_messages() {
+        let chat = Chat::default();
+        assert_eq!(chat.check("p", "  hi  ").unwrap(), "hi");
+        assert!(matches!(chat.check("p", "   "), Err(Error::InvalidMessage)));
+        let long = "a".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert!(matches!(chat.check("p", &long), Err(Error::InvalidMessage)));
+    }
+
+    #[test]
+    fn check_limits_the_rate_of_each_sender() {
+        let chat = Chat::default();
+        for _ in 0..RATE_LIMIT {
+            chat.check("p", "hi").unwrap();
+        }
+        assert!(matches!(chat.check("p", "hi"), Err(Error::RateLimited)));
+        chat.check("q", "hi").unwrap();
+        chat.forget("p");
+        chat.check("p", "hi").unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::achievements::{Achievement, Progress};
+use crate::board::Sunk;
+use crate::chat::Message;
+use crate::game::{Clock, GameState, LobbyRoom, Reason, Rules, Score, Shot, SpectatorState};
+
+/// A single shot of a classic game, sent to everyone in the room.
+#[derive(Debug, Serialize)]
+pub struct Attacked {
+    pub by: String,
+    pub at: (usize, usize),
+    pub hit: bool,
+    pub sunk: Option<Sunk>,
+    pub game_over: bool,
+    /// Time left after the shot, in rooms with time controls.
+    pub clock: Option<Clock>,
+}
+
+/// A whole volley of a salvo game, sent to everyone in the room.
+#[derive(Debug, Serialize)]
+pub struct Volley {
+    pub by: String,
+    pub shots: Vec<Shot>,
+    pub game_over: bool,
+    pub clock: Option<Clock>,
+}
+
+/// End of a game, sent to everyone in the room however it ended.
+#[derive(Debug, Serialize)]
+pub struct GameOver<'a> {
+    pub winner: &'a str,
+    pub reason: Reason,
+}
+
+/// Everything a returning player needs to draw the room again.
+#[derive(Debug, Serialize)]
+pub struct Restore {
+    /// `None` while they wait for an opponent or the fleets are still being placed.
+    #[serde(flatten)]
+    pub state: Option<GameState>,
+    pub rules: Rules,
+    pub score: Score,
+    /// Recent chat of the room, oldest first.
+    pub messages: Vec<Message>,
+    pub clock: Option<Clock>,
+}
+
+/// Snapshot for spectators, sent when they start watching and again once the game is over.
+#[derive(Debug, Serialize)]
+pub struct Spectating {
+    #[serde(flatten)]
+    pub state: SpectatorState,
+    pub rules: Rules,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomUpdate<'a> {
+    pub room: &'a str,
+    /// Players in the room, spectators not included.
+    pub users: usize,
+    pub spectators: usize,
+    /// `None` once the room has closed.
+    pub score: Option<Score>,
+    /// Display names of the players signed in to an account, by player id.
+    pub names: HashMap<String, String>,
+}
+
+/// Open public rooms, sent to everyone browsing the lobby whenever one opens, fills or closes.
+#[derive(Debug, Serialize)]
+pub struct Lobby {
+    pub rooms: Vec<LobbyRoom>,
+}
+
+/// An achievement a player of the room unlocked for the first time, sent as the game ends.
+#[derive(Debug, Serialize)]
+pub struct AchievementUnlocked<'a> {
+    pub player: &'a str,
+    pub achievement: &'static Achievement,
+}
+
+/// The achievement catalogue with what an account has unlocked.
+#[derive(Debug, Serialize)]
+pub struct Achievements {
+    pub achievements: Vec<Progress>,
+}
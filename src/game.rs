@@ -1,11 +1,26 @@
+use rand::seq::SliceRandom;
 use rand::Rng;
-use serde::Serialize;
-use socketioxide::socket::Sid;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::board::Board;
+use crate::achievements::unlock_achievements;
+use crate::board::density::{heatmap, remaining, unexplored, Heatmap};
+use crate::board::{self, Board, Layout, Sunk, CLASSIC_FLEET};
+use crate::bot::{bot_id, Difficulty};
+use crate::ratings::rate_game;
+use crate::stats::record_game;
 
 pub const ROOM_CODE_LENGTH: usize = 4;
+pub const MIN_BOARD_SIZE: usize = 5;
+pub const MAX_BOARD_SIZE: usize = 20;
+pub const MAX_FLEET_SIZE: usize = 20;
+pub const MAX_BEST_OF: usize = 9;
+/// Longest move limit or starting clock, in seconds.
+pub const MAX_TIME_CONTROL: u32 = 3600;
+/// Longest room password, in characters.
+pub const MAX_PASSWORD_LENGTH: usize = 64;
+pub const INVITE_TOKEN_LENGTH: usize = 24;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -15,6 +30,8 @@ pub enum Error {
     RoomFull(Option<String>),
     #[error("Room not full")]
     RoomNotFull,
+    #[error("Not your turn")]
+    NotYourTurn,
     #[error("GameOver room joined")]
     GameOverRoom,
     #[error("Already in room")]
@@ -23,14 +40,86 @@ pub enum Error {
     NotInRoom,
     #[error("Invalid Move")]
     InvalidMove,
+    #[error("Move not allowed in {0:?} mode")]
+    WrongMode(Mode),
     #[error("Code Generation Limit Reached")]
     CodeGenerationLimitReached,
+    #[error("Invalid Rules")]
+    InvalidRules,
+    #[error("Room not found")]
+    RoomNotFound,
+    #[error("Game not found")]
+    GameNotFound,
+    #[error("Game not over")]
+    GameNotOver,
+    #[error("No game in progress")]
+    NoGameInProgress,
+    #[error("No rematch requested")]
+    NoRematchRequest,
+    #[error("Message empty or too long")]
+    InvalidMessage,
+    #[error("Too many messages")]
+    RateLimited,
+    #[error("Password too short or too long")]
+    InvalidPassword,
+    #[error("Wrong password or invite")]
+    WrongCredentials,
+    #[error("Invalid username")]
+    InvalidUsername,
+    #[error("Username taken")]
+    UsernameTaken,
+    #[error("Invalid display name")]
+    InvalidDisplayName,
+    #[error("Account not found")]
+    AccountNotFound,
+    #[error("Malformed request")]
+    InvalidRequest,
+    #[error("Hints not allowed in this room")]
+    HintsDisabled,
+    #[error("Invalid Board: {0}")]
+    InvalidBoard(#[from] board::Invalid),
     #[error("SQL Error\n{0:?}")]
     Sqlx(#[from] sqlx::Error),
 }
 
-#[derive(Debug, sqlx::Type, PartialEq, Serialize)]
+impl Error {
+    /// Stable name of the error for clients that are programs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::RoomFull(_) => "room_full",
+            Error::RoomNotFull => "room_not_full",
+            Error::NotYourTurn => "not_your_turn",
+            Error::GameOverRoom => "game_over_room",
+            Error::AlreadyInRoom => "already_in_room",
+            Error::NotInRoom => "not_in_room",
+            Error::InvalidMove => "invalid_move",
+            Error::WrongMode(_) => "wrong_mode",
+            Error::CodeGenerationLimitReached => "code_generation_limit_reached",
+            Error::InvalidRules => "invalid_rules",
+            Error::RoomNotFound => "room_not_found",
+            Error::GameNotFound => "game_not_found",
+            Error::GameNotOver => "game_not_over",
+            Error::NoGameInProgress => "no_game_in_progress",
+            Error::NoRematchRequest => "no_rematch_request",
+            Error::InvalidMessage => "invalid_message",
+            Error::RateLimited => "rate_limited",
+            Error::InvalidPassword => "invalid_password",
+            Error::WrongCredentials => "wrong_credentials",
+            Error::InvalidUsername => "invalid_username",
+            Error::UsernameTaken => "username_taken",
+            Error::InvalidDisplayName => "invalid_display_name",
+            Error::AccountNotFound => "account_not_found",
+            Error::InvalidRequest => "invalid_request",
+            Error::HintsDisabled => "hints_disabled",
+            Error::InvalidBoard(_) => "invalid_board",
+            Error::Sqlx(_) => "internal",
+        }
+    }
+}
+
+#[derive(Debug, sqlx::Type, PartialEq, Serialize, JsonSchema)]
 #[sqlx(type_name = "STAT", rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum Status {
     Waiting,
     P1Turn,
@@ -38,6 +127,518 @@ pub enum Status {
     GameOver,
 }
 
+#[derive(
+    Debug, Clone, Copy, Default, sqlx::Type, PartialEq, Serialize, Deserialize, JsonSchema,
+)]
+#[sqlx(type_name = "MODE", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// One shot per turn.
+    #[default]
+    Classic,
+    /// As many shots per turn as the shooter has ships afloat, revealed together.
+    Salvo,
+}
+
+/// When a player gets to shoot again in classic mode.
+#[derive(
+    Debug, Clone, Copy, Default, sqlx::Type, PartialEq, Serialize, Deserialize, JsonSchema,
+)]
+#[sqlx(type_name = "TURN_POLICY", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TurnPolicy {
+    #[default]
+    BonusOnHit,
+    Alternate,
+    BonusOnSink,
+}
+
+impl TurnPolicy {
+    pub fn keeps_turn(self, hit: bool, sunk: bool) -> bool {
+        match self {
+            TurnPolicy::BonusOnHit => hit,
+            TurnPolicy::Alternate => false,
+            TurnPolicy::BonusOnSink => sunk,
+        }
+    }
+}
+
+/// How a game came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// The loser's whole fleet was sunk.
+    FleetSunk,
+    Resigned,
+    /// The loser stayed disconnected past the grace period.
+    Abandoned,
+    /// The loser ran out of time in a room that forfeits on timeout.
+    TimedOut,
+}
+
+/// What happens to a player who runs out of time.
+#[derive(
+    Debug, Clone, Copy, Default, sqlx::Type, PartialEq, Serialize, Deserialize, JsonSchema,
+)]
+#[sqlx(type_name = "ON_TIMEOUT", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OnTimeout {
+    /// A random shot, or volley, is fired for the player.
+    RandomShot,
+    #[default]
+    Forfeit,
+}
+
+/// Chess-style time controls, in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TimeControl {
+    /// Time for each move, no limit when `None`.
+    pub move_limit: Option<u32>,
+    /// Time on each player's clock at the start of a game, no clock when `None`.
+    pub clock: Option<u32>,
+    /// Time added to the clock of a player after each of their moves.
+    pub increment: u32,
+    pub on_timeout: OnTimeout,
+}
+
+/// Time left in a room with time controls, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Clock {
+    pub players: [Option<String>; 2],
+    /// Left on the clock of player 1 and player 2.
+    pub remaining: [Option<i64>; 2],
+    /// Left for the move being played.
+    pub move_left: Option<i64>,
+    /// Index of the player to move, while a game is in progress.
+    #[serde(skip)]
+    turn: Option<usize>,
+}
+
+impl Clock {
+    /// The clocks `elapsed` milliseconds into the move of the player at `turn`, with the move
+    /// limit in seconds.
+    fn running(
+        players: [Option<String>; 2],
+        turn: Option<usize>,
+        mut remaining: [Option<i64>; 2],
+        move_limit: Option<i32>,
+        elapsed: i64,
+    ) -> Clock {
+        let mut move_left = None;
+        if let Some(turn) = turn {
+            // the clock of the player to move is running
+            remaining[turn] = remaining[turn].map(|clock| (clock - elapsed).max(0));
+            move_left = move_limit.map(|limit| (limit as i64 * 1000 - elapsed).max(0));
+        }
+        Clock {
+            players,
+            remaining,
+            move_left,
+            turn,
+        }
+    }
+
+    /// Time until the player to move runs out, `None` between games.
+    pub fn expires_in(&self) -> Option<i64> {
+        let turn = self.turn?;
+        match (self.remaining[turn], self.move_left) {
+            (Some(clock), Some(limit)) => Some(clock.min(limit)),
+            (clock, limit) => clock.or(limit),
+        }
+    }
+
+    /// The player to move.
+    pub fn player(&self) -> Option<&str> {
+        self.turn.and_then(|turn| self.players[turn].as_deref())
+    }
+}
+
+/// Result of a single shot of a salvo.
+#[derive(Debug, Clone, Serialize)]
+pub struct Shot {
+    pub at: (usize, usize),
+    pub hit: bool,
+    pub sunk: Option<Sunk>,
+}
+
+/// A shot in the log of a game.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Move {
+    pub number: i32,
+    pub shooter: String,
+    pub at: (usize, usize),
+    pub hit: bool,
+    /// Name of the ship the shot sank.
+    pub sunk: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub time: i64,
+}
+
+/// A player who took over an abandoned seat in a game in progress.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Substitution {
+    /// 1 or 2, the seat of the player it took over from.
+    pub seat: i16,
+    pub player: String,
+    /// Number of the first move played after taking the seat.
+    pub from_move: i32,
+}
+
+/// Everything needed to play a game back move by move.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Replay {
+    pub game: i32,
+    pub room: String,
+    /// The players who started the game.
+    pub players: [String; 2],
+    pub substitutions: Vec<Substitution>,
+    /// Fleets as placed, revealed once the game has ended. One string per row, one char per
+    /// cell: 'e'mpty, 's'hip, 'h'it or 'm'iss.
+    pub boards: Option<[Vec<String>; 2]>,
+    pub winner: Option<String>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+    pub moves: Vec<Move>,
+}
+
+/// Where a room stands, for those outside it.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RoomInfo {
+    pub code: String,
+    pub status: Status,
+    pub rules: Rules,
+    pub score: Score,
+    /// Listed in the lobby.
+    pub public: bool,
+    /// Seats need the password or an invite.
+    pub restricted: bool,
+    /// The game in progress, or the last one played in the room.
+    pub game: Option<i32>,
+}
+
+/// What anyone may know of a game, played or in progress.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GameSummary {
+    pub game: i32,
+    pub room: String,
+    /// The players who started the game.
+    pub players: [String; 2],
+    pub substitutions: Vec<Substitution>,
+    /// Display names of the players who were signed in.
+    pub names: [Option<String>; 2],
+    /// Shots fired from each seat, substitutes included.
+    pub shots: [i64; 2],
+    pub winner: Option<String>,
+    pub started_at: i64,
+    pub ended_at: Option<i64>,
+}
+
+/// Who besides its creator may take a seat in a room, anyone when nothing is set.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct Access {
+    pub password: Option<String>,
+    /// Only players with an invite from someone in the room, or the password if there is one.
+    pub invite_only: bool,
+}
+
+impl Access {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(password) = &self.password {
+            if password.is_empty() || password.chars().count() > MAX_PASSWORD_LENGTH {
+                return Err(Error::InvalidPassword);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.password.is_some() || self.invite_only
+    }
+}
+
+/// What a player shows to take a seat in a restricted room.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Credentials {
+    pub password: Option<String>,
+    pub invite: Option<String>,
+}
+
+/// A public room waiting for an opponent, as listed in the lobby.
+#[derive(Debug, Serialize)]
+pub struct LobbyRoom {
+    pub code: String,
+    pub rules: Rules,
+    /// Seconds since the room was created.
+    pub open_for: i64,
+}
+
+/// A game as one of its players sees it.
+#[derive(Debug, Serialize)]
+pub struct GameState {
+    /// Whether it is this player's turn.
+    pub turn: bool,
+    pub player: Vec<String>,
+    pub opponent: Vec<String>,
+    pub game_over: bool,
+    /// Set once the game is over, fleets are not all sunk when a player resigns.
+    pub winner: Option<String>,
+}
+
+/// A game as spectators see it, neither fleet is revealed before the game is over.
+#[derive(Debug, Serialize)]
+pub struct SpectatorState {
+    pub players: [Option<String>; 2],
+    /// Boards of player 1 and player 2, empty until they are placed.
+    pub boards: [Vec<String>; 2],
+    /// Player whose turn it is.
+    pub turn: Option<String>,
+    pub game_over: bool,
+}
+
+/// Games won by each player of the room in the current series.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Score {
+    pub players: [Option<String>; 2],
+    pub wins: [usize; 2],
+    pub best_of: usize,
+}
+
+impl Score {
+    /// Whether a player has won more than half of the series.
+    pub fn is_decided(&self) -> bool {
+        self.wins.iter().any(|&wins| wins > self.best_of / 2)
+    }
+}
+
+/// Settings chosen by the creator of a room, fixed for the lifetime of the room.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(default)]
+pub struct Rules {
+    pub width: usize,
+    pub height: usize,
+    /// Lengths of the ships each player places.
+    pub fleet: Vec<usize>,
+    pub mode: Mode,
+    pub turn_policy: TurnPolicy,
+    /// Difficulty of the computer playing as player 2, `None` in rooms between two people.
+    pub bot: Option<Difficulty>,
+    /// Games in a series of rematches, the first to win a majority takes the series.
+    pub best_of: usize,
+    pub time_control: Option<TimeControl>,
+    /// Whether players may ask where the opponent's ships likely are, never in matchmade games.
+    pub hints: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            width: 10,
+            height: 10,
+            fleet: CLASSIC_FLEET.to_vec(),
+            mode: Mode::Classic,
+            turn_policy: TurnPolicy::BonusOnHit,
+            bot: None,
+            best_of: 5,
+            time_control: None,
+            hints: false,
+        }
+    }
+}
+
+impl Rules {
+    pub fn validate(&self) -> Result<()> {
+        let size = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+        if !size.contains(&self.width) || !size.contains(&self.height) {
+            return Err(Error::InvalidRules);
+        }
+        let longest = self.width.max(self.height);
+        if self.fleet.is_empty()
+            || self.fleet.len() > MAX_FLEET_SIZE
+            || self.fleet.iter().any(|&ship| ship == 0 || ship > longest)
+        {
+            return Err(Error::InvalidRules);
+        }
+        if self.best_of.is_multiple_of(2) || self.best_of > MAX_BEST_OF {
+            return Err(Error::InvalidRules);
+        }
+        if let Some(time) = self.time_control {
+            let valid = 1..=MAX_TIME_CONTROL;
+            if time.move_limit.is_none() && time.clock.is_none()
+                || time.move_limit.is_some_and(|limit| !valid.contains(&limit))
+                || time.clock.is_some_and(|clock| !valid.contains(&clock))
+                || time.increment > MAX_TIME_CONTROL
+            {
+                return Err(Error::InvalidRules);
+            }
+        }
+        // the computer only plays one shot at a time
+        if self.bot.is_some() && self.mode != Mode::Classic {
+            return Err(Error::InvalidRules);
+        }
+        // the fleet must be placeable with ships not touching each other
+        if Board::fit(self.height, self.width, &self.fleet).is_none() {
+            return Err(Error::InvalidRules);
+        }
+        Ok(())
+    }
+
+    /// Whether these are the standard board and fleet between two people, the only rules rated
+    /// games are played by, in either mode and with any turn policy.
+    pub fn is_standard(&self) -> bool {
+        let standard = Rules::default();
+        self.width == standard.width
+            && self.height == standard.height
+            && self.fleet == standard.fleet
+            && self.bot.is_none()
+            && !self.hints
+    }
+}
+
+pub async fn get_rules(code: &str, pool: &sqlx::PgPool) -> Result<Rules> {
+    read_rules(code, pool).await
+}
+
+async fn read_rules(code: &str, executor: impl sqlx::PgExecutor<'_>) -> Result<Rules> {
+    let room = sqlx::query!(
+        r#"SELECT width, height, fleet, mode AS "mode: Mode", turn_policy AS "turn_policy: TurnPolicy", bot AS "bot: Difficulty", best_of, move_limit, clock, increment, on_timeout AS "on_timeout: OnTimeout", hints FROM rooms WHERE code = $1"#,
+        code
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(Rules {
+        width: room.width as usize,
+        height: room.height as usize,
+        fleet: room.fleet.into_iter().map(|ship| ship as usize).collect(),
+        mode: room.mode,
+        turn_policy: room.turn_policy,
+        bot: room.bot,
+        best_of: room.best_of as usize,
+        time_control: (room.move_limit.is_some() || room.clock.is_some()).then_some(TimeControl {
+            move_limit: room.move_limit.map(|limit| limit as u32),
+            clock: room.clock.map(|clock| clock as u32),
+            increment: room.increment as u32,
+            on_timeout: room.on_timeout,
+        }),
+        hints: room.hints,
+    })
+}
+
+/// The clocks of the room, `None` in rooms without time controls.
+pub async fn get_clock(code: &str, pool: &sqlx::PgPool) -> Result<Option<Clock>> {
+    let room = sqlx::query!(
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status", move_limit, clock1, clock2, (EXTRACT(EPOCH FROM NOW() - move_started) * 1000)::BIGINT AS elapsed FROM rooms WHERE code = $1"#,
+        code
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
+    if room.move_limit.is_none() && room.clock1.is_none() {
+        return Ok(None);
+    }
+    let turn = match room.stat {
+        Status::P1Turn => Some(0),
+        Status::P2Turn => Some(1),
+        _ => None,
+    };
+    Ok(Some(Clock::running(
+        [room.player1_id, room.player2_id],
+        turn,
+        [room.clock1, room.clock2],
+        room.move_limit,
+        room.elapsed.unwrap_or(0),
+    )))
+}
+
+/// Picks random shots for the player to move, as many as a volley of theirs needs in salvo mode.
+pub async fn random_targets(sid: &str, pool: &sqlx::PgPool) -> Result<Vec<(usize, usize)>> {
+    let code = room_if_player_exists(sid, pool)
+        .await?
+        .ok_or(Error::NotInRoom)?;
+    let rules = get_rules(&code, pool).await?;
+    let own: Board = sqlx::query!(r"SELECT board FROM players WHERE id = $1", sid)
+        .fetch_one(pool)
+        .await?
+        .board
+        .ok_or(Error::InvalidMove)?
+        .into();
+    let opponent = get_game_state(sid, &code, pool).await?.opponent;
+    let mut cells = unexplored(&opponent);
+    let count = match rules.mode {
+        Mode::Classic => 1,
+        Mode::Salvo => own.ships_afloat(),
+    };
+    let (targets, _) = cells.partial_shuffle(&mut rand::thread_rng(), count);
+    Ok(targets.to_vec())
+}
+
+pub async fn get_score(code: &str, pool: &sqlx::PgPool) -> Result<Score> {
+    let room = sqlx::query!(
+        r"SELECT player1_id, player2_id, score1, score2, best_of FROM rooms WHERE code = $1",
+        code
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
+    Ok(Score {
+        players: [room.player1_id, room.player2_id],
+        wins: [room.score1 as usize, room.score2 as usize],
+        best_of: room.best_of as usize,
+    })
+}
+
+pub async fn room_info(code: &str, pool: &sqlx::PgPool) -> Result<RoomInfo> {
+    let room = sqlx::query!(
+        r#"SELECT code, stat AS "stat: Status", public, password_hash IS NOT NULL OR invite_only AS "restricted!", game_id FROM rooms WHERE code = $1"#,
+        code
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
+    Ok(RoomInfo {
+        rules: get_rules(code, pool).await?,
+        score: get_score(code, pool).await?,
+        code: room.code,
+        status: room.stat,
+        public: room.public,
+        restricted: room.restricted,
+        game: room.game_id,
+    })
+}
+
+pub async fn game_summary(id: i32, pool: &sqlx::PgPool) -> Result<GameSummary> {
+    let game = sqlx::query!(
+        r#"SELECT g.room_code, g.player1_id, g.player2_id, a1.display_name AS "name1?", a2.display_name AS "name2?", (SELECT COUNT(*) FROM moves m WHERE m.game_id = g.id AND (m.shooter = g.player1_id OR m.shooter IN (SELECT substitute FROM substitutions s WHERE s.game_id = g.id AND s.seat = 1))) AS "shots1!", (SELECT COUNT(*) FROM moves m WHERE m.game_id = g.id AND (m.shooter = g.player2_id OR m.shooter IN (SELECT substitute FROM substitutions s WHERE s.game_id = g.id AND s.seat = 2))) AS "shots2!", g.winner, (EXTRACT(EPOCH FROM g.started_at) * 1000)::BIGINT AS "started_at!", (EXTRACT(EPOCH FROM g.ended_at) * 1000)::BIGINT AS ended_at FROM games g LEFT JOIN accounts a1 ON a1.id = g.account1_id LEFT JOIN accounts a2 ON a2.id = g.account2_id WHERE g.id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::GameNotFound)?;
+    Ok(GameSummary {
+        game: id,
+        room: game.room_code,
+        players: [game.player1_id, game.player2_id],
+        substitutions: substitutions(id, pool).await?,
+        names: [game.name1, game.name2],
+        shots: [game.shots1, game.shots2],
+        winner: game.winner,
+        started_at: game.started_at,
+        ended_at: game.ended_at,
+    })
+}
+
+async fn substitutions(game: i32, pool: &sqlx::PgPool) -> Result<Vec<Substitution>> {
+    Ok(sqlx::query_as!(
+        Substitution,
+        r#"SELECT seat, substitute AS player, from_move FROM substitutions WHERE game_id = $1 ORDER BY from_move"#,
+        game
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
 pub async fn room_if_player_exists(sid: &str, pool: &sqlx::PgPool) -> Result<Option<String>> {
     Ok(
         sqlx::query!("SELECT room_code FROM players WHERE id = $1", sid)
@@ -65,48 +666,186 @@ async fn generate_code(pool: &sqlx::PgPool) -> Result<String> {
     Err(Error::CodeGenerationLimitReached)
 }
 
-pub async fn add_room(sid: Sid, pool: &sqlx::PgPool) -> Result<String> {
-    delete_sid(sid.as_str(), pool).await?;
+/// Creates a room with the player in its first seat, restricted from the start if `access`
+/// says so, and `matchmade` when the queue paired its players.
+pub async fn add_room(
+    sid: &str,
+    rules: &Rules,
+    access: &Access,
+    matchmade: bool,
+    pool: &sqlx::PgPool,
+) -> Result<String> {
+    rules.validate()?;
+    access.validate()?;
     let code = generate_code(pool).await?;
+    let time = rules.time_control.unwrap_or_default();
 
+    let mut txn = pool.begin().await?;
+    sqlx::query!(r"DELETE FROM players WHERE id = $1", sid)
+        .execute(&mut *txn)
+        .await?;
     sqlx::query!(
-        r"WITH new_user AS (INSERT INTO players (id, room_code) VALUES ($1, $2) RETURNING id) INSERT INTO rooms (player1_id, code) SELECT $1, $2 FROM new_user",
-        sid.as_str(),
-        code
+        r"WITH new_user AS (INSERT INTO players (id, room_code) VALUES ($1, $2) RETURNING id) INSERT INTO rooms (player1_id, code, width, height, fleet, mode, turn_policy, bot, best_of, move_limit, clock, increment, on_timeout, hints, password_hash, invite_only, matchmade) SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, crypt($15, gen_salt('bf')), $16, $17 FROM new_user",
+        sid,
+        code,
+        rules.width as i16,
+        rules.height as i16,
+        &rules.fleet.iter().map(|&ship| ship as i16).collect::<Vec<_>>(),
+        rules.mode as Mode,
+        rules.turn_policy as TurnPolicy,
+        rules.bot as Option<Difficulty>,
+        rules.best_of as i16,
+        time.move_limit.map(|limit| limit as i32),
+        time.clock.map(|clock| clock as i32),
+        time.increment as i32,
+        time.on_timeout as OnTimeout,
+        rules.hints,
+        access.password,
+        access.invite_only,
+        matchmade
     )
-    .execute(pool)
+    .execute(&mut *txn)
     .await?;
+    txn.commit().await?;
+    if rules.bot.is_some() {
+        add_bot(&code, rules, pool).await?;
+    }
     Ok(code)
 }
 
-pub async fn join_room(sid: Sid, code: String, pool: &sqlx::PgPool) -> Result<()> {
-    let code = code.to_uppercase();
+/// Lists the room in the lobby until it fills.
+pub async fn make_public(code: &str, pool: &sqlx::PgPool) -> Result<()> {
+    sqlx::query!(r"UPDATE rooms SET public = TRUE WHERE code = $1", code)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Checks that a spectator may watch the room, with its password or an invite to it, which
+/// stays unused for the seat it was meant for.
+pub async fn check_watch(code: &str, credentials: &Credentials, pool: &sqlx::PgPool) -> Result<()> {
     let room = sqlx::query!(
-        r#"SELECT player1_id, player2_id, stat AS "stat: Status" FROM rooms WHERE code = $1"#,
+        r#"SELECT password_hash IS NOT NULL OR invite_only AS "restricted!", COALESCE(password_hash = crypt($2, password_hash), FALSE) AS "password_ok!", EXISTS (SELECT 1 FROM invites WHERE room_code = $1 AND token_hash = digest($3, 'sha256')) AS "invited!" FROM rooms WHERE code = $1"#,
+        code,
+        credentials.password,
+        credentials.invite
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
+    if room.restricted && !room.password_ok && !room.invited {
+        return Err(Error::WrongCredentials);
+    }
+    Ok(())
+}
+
+/// Creates a single-use invite to the room of the player, returning its token.
+pub async fn create_invite(sid: &str, pool: &sqlx::PgPool) -> Result<String> {
+    let code = room_if_player_exists(sid, pool)
+        .await?
+        .ok_or(Error::NotInRoom)?;
+    let token: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(INVITE_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    sqlx::query!(
+        r"INSERT INTO invites (token_hash, room_code) VALUES (digest($1, 'sha256'), $2)",
+        token,
         code
     )
-    .fetch_one(pool)
+    .execute(pool)
+    .await?;
+    Ok(token)
+}
+
+/// Uses up an invite to the room, failing if there is no such invite.
+async fn use_invite(code: &str, token: &str, pool: &sqlx::PgPool) -> Result<()> {
+    let used = sqlx::query!(
+        r"DELETE FROM invites WHERE room_code = $1 AND token_hash = digest($2, 'sha256')",
+        code,
+        token
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if used == 0 {
+        return Err(Error::WrongCredentials);
+    }
+    Ok(())
+}
+
+/// Public rooms with a player waiting for an opponent, oldest first.
+pub async fn get_lobby(pool: &sqlx::PgPool) -> Result<Vec<LobbyRoom>> {
+    let rooms = sqlx::query!(
+        r#"SELECT code, EXTRACT(EPOCH FROM NOW() - created_at)::BIGINT AS "open_for!" FROM rooms WHERE public AND password_hash IS NULL AND NOT invite_only AND stat = $1 AND (player1_id IS NULL) != (player2_id IS NULL) AND NOT EXISTS (SELECT 1 FROM players WHERE players.room_code = rooms.code AND abandoned) ORDER BY created_at"#,
+        Status::Waiting as Status
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut lobby = Vec::with_capacity(rooms.len());
+    for room in rooms {
+        // the room may have closed since
+        let Ok(rules) = get_rules(&room.code, pool).await else {
+            continue;
+        };
+        lobby.push(LobbyRoom {
+            rules,
+            code: room.code,
+            open_for: room.open_for,
+        });
+    }
+    Ok(lobby)
+}
+
+/// Seats the computer as player 2 with a freshly placed fleet, again on every rematch.
+pub async fn add_bot(code: &str, rules: &Rules, pool: &sqlx::PgPool) -> Result<()> {
+    let board: Vec<String> = Board::randomize(rules.height, rules.width, &rules.fleet)
+        .ok_or(Error::InvalidRules)?
+        .into();
+    let id = bot_id(code);
+    let mut txn = pool.begin().await?;
+    sqlx::query!(
+        r"INSERT INTO players (id, room_code, board) VALUES ($1, $2, $3) ON CONFLICT (id) DO UPDATE SET board = $3",
+        id,
+        code,
+        &board
+    )
+    .execute(&mut *txn)
+    .await?;
+    sqlx::query!(
+        r"UPDATE rooms SET player2_id = $1 WHERE code = $2",
+        id,
+        code
+    )
+    .execute(&mut *txn)
     .await?;
+    txn.commit().await?;
+    Ok(())
+}
 
-    let sid = sid.as_str();
+pub async fn join_room(
+    sid: &str,
+    code: String,
+    credentials: &Credentials,
+    pool: &sqlx::PgPool,
+) -> Result<()> {
+    let code = code.to_uppercase();
+    let room = sqlx::query!(
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status", password_hash IS NOT NULL OR invite_only AS "restricted!", COALESCE(password_hash = crypt($2, password_hash), FALSE) AS "password_ok!" FROM rooms WHERE code = $1"#,
+        code,
+        credentials.password
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
 
-    // if player is already in room
+    // a new game in the same room goes through `request_rematch`
     if [room.player1_id.as_ref(), room.player2_id.as_ref()]
         .into_iter()
         .flatten()
         .any(|x| x == sid)
     {
-        // if game was over, set status to waiting and return
-        if room.stat == Status::GameOver {
-            sqlx::query!(
-                r"UPDATE rooms SET stat = $1 WHERE code = $2",
-                Status::Waiting as Status,
-                code
-            )
-            .execute(pool)
-            .await?;
-            return Ok(());
-        }
         return Err(Error::AlreadyInRoom);
     }
 
@@ -114,15 +853,27 @@ pub async fn join_room(sid: Sid, code: String, pool: &sqlx::PgPool) -> Result<()
         return Err(Error::GameOverRoom);
     }
 
+    let mut replacing = None;
     if let (Some(p1), Some(p2)) = (room.player1_id.as_ref(), room.player2_id.as_ref()) {
         if in_delete_sid(p1, pool).await? {
-            // update_sid(p1, sid, pool).await?;
-            return Err(Error::RoomFull(Some(p1.to_string())));
+            replacing = Some(p1.to_string());
         } else if in_delete_sid(p2, pool).await? {
-            // update_sid(p2, sid, pool).await?;
-            return Err(Error::RoomFull(Some(p2.to_string())));
+            replacing = Some(p2.to_string());
+        } else {
+            return Err(Error::RoomFull(None));
         }
-        return Err(Error::RoomFull(None));
+    }
+
+    // taking over a player who left needs the credentials as much as a free seat does
+    if room.restricted && !room.password_ok {
+        let invite = credentials
+            .invite
+            .as_deref()
+            .ok_or(Error::WrongCredentials)?;
+        use_invite(&code, invite, pool).await?;
+    }
+    if let Some(player) = replacing {
+        return Err(Error::RoomFull(Some(player)));
     }
     delete_sid(sid, pool).await?;
     let mut txn = pool.begin().await?;
@@ -150,91 +901,136 @@ pub async fn join_room(sid: Sid, code: String, pool: &sqlx::PgPool) -> Result<()
     Ok(())
 }
 
-pub async fn get_room(sid: Sid, pool: &sqlx::PgPool) -> Result<Option<String>> {
+pub async fn get_room(sid: &str, pool: &sqlx::PgPool) -> Result<Option<String>> {
     Ok(
-        sqlx::query!("SELECT room_code FROM players WHERE id = $1", sid.as_str())
+        sqlx::query!("SELECT room_code FROM players WHERE id = $1", sid)
             .fetch_optional(pool)
             .await?
             .map(|r| r.room_code),
     )
 }
 
-pub async fn add_board(sid: Sid, board: Board, pool: &sqlx::PgPool) -> Result<()> {
+pub async fn add_board(sid: &str, layout: Layout, pool: &sqlx::PgPool) -> Result<()> {
+    let room = get_room(sid, pool).await?.ok_or(Error::NotInRoom)?;
+    let rules = get_rules(&room, pool).await?;
+    let board = Board::from_layout(&layout, rules.height, rules.width, &rules.fleet)?;
     let board: Vec<String> = board.into();
-    sqlx::query!(
-        "UPDATE players SET board = $1 WHERE id = $2",
-        &board,
-        sid.as_str()
-    )
-    .execute(pool)
-    .await?;
+    sqlx::query!("UPDATE players SET board = $1 WHERE id = $2", &board, sid)
+        .execute(pool)
+        .await?;
     Ok(())
 }
 
-pub async fn get_game_state(
-    sid: &str,
-    room: &str,
-    pool: &sqlx::PgPool,
-) -> Result<(bool, Vec<String>, Vec<String>, bool)> {
+pub async fn get_game_state(sid: &str, room: &str, pool: &sqlx::PgPool) -> Result<GameState> {
     let room_details = sqlx::query!(
-        r#"SELECT player1_id, player2_id, stat AS "stat: Status" FROM rooms WHERE code = $1"#,
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status", (SELECT winner FROM games WHERE id = game_id) AS winner, (SELECT board FROM players WHERE id = player1_id AND room_code = code) AS board1, (SELECT board FROM players WHERE id = player2_id AND room_code = code) AS board2 FROM rooms WHERE code = $1"#,
         room
     )
-    .fetch_one(pool)
-    .await?;
-
-    let turn = match room_details.stat {
-        Status::P1Turn if room_details.player1_id == Some(sid.to_string()) => true,
-        Status::P2Turn if room_details.player2_id == Some(sid.to_string()) => true,
-        _ => false,
-    };
-
-    let oid = match (room_details.player1_id, room_details.player2_id) {
-        (Some(p1), Some(p2)) if p1 == sid => p2,
-        (Some(p1), Some(p2)) if p2 == sid => p1,
-        _ => return Err(Error::NotInRoom),
-    };
-
-    let player_board: Board = sqlx::query!(
-        r#"SELECT board FROM players WHERE id = $1 AND room_code = $2"#,
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::RoomNotFound)?;
+    let fleet = get_rules(room, pool).await?.fleet;
+    GameState::of(
         sid,
-        room
+        [room_details.player1_id, room_details.player2_id],
+        [room_details.board1, room_details.board2],
+        room_details.stat,
+        room_details.winner,
+        &fleet,
     )
-    .fetch_one(pool)
-    .await?
-    .board
-    .unwrap()
-    .into();
+}
 
-    let opponent_board: Board = sqlx::query!(
-        r#"SELECT board FROM players WHERE id = $1 AND room_code = $2"#,
-        oid,
+impl GameState {
+    /// The game as the player in one of the seats sees it, failing while they wait for an
+    /// opponent or a fleet is still being placed.
+    fn of(
+        sid: &str,
+        seats: [Option<String>; 2],
+        boards: [Option<Vec<String>>; 2],
+        stat: Status,
+        winner: Option<String>,
+        fleet: &[usize],
+    ) -> Result<GameState> {
+        let seat = match &seats {
+            [Some(p1), Some(_)] if p1 == sid => 0,
+            [Some(_), Some(p2)] if p2 == sid => 1,
+            _ => return Err(Error::NotInRoom),
+        };
+        let [board1, board2] = boards;
+        let (Some(board1), Some(board2)) = (board1, board2) else {
+            return Err(Error::NoGameInProgress);
+        };
+        let mut boards = [Board::from(board1), Board::from(board2)];
+        if seat == 1 {
+            boards.reverse();
+        }
+        let [player_board, opponent_board] = boards;
+        let game_over = stat == Status::GameOver;
+        let turn = matches!((stat, seat), (Status::P1Turn, 0) | (Status::P2Turn, 1));
+        Ok(GameState {
+            turn,
+            player: player_board.mark_redundant(fleet).view(true),
+            opponent: opponent_board.mark_redundant(fleet).view(false),
+            game_over,
+            winner: winner.filter(|_| game_over),
+        })
+    }
+}
+
+/// Where the opponent's remaining ships most likely are, from what the player has seen of them.
+pub async fn hint(sid: &str, pool: &sqlx::PgPool) -> Result<Heatmap> {
+    let room = get_room(sid, pool).await?.ok_or(Error::NotInRoom)?;
+    let rules = get_rules(&room, pool).await?;
+    if !rules.hints {
+        return Err(Error::HintsDisabled);
+    }
+    let opponent = get_game_state(sid, &room, pool).await?.opponent;
+    Ok(heatmap(&opponent, &remaining(&opponent, &rules.fleet)))
+}
+
+pub async fn get_spectator_state(room: &str, pool: &sqlx::PgPool) -> Result<SpectatorState> {
+    let room_details = sqlx::query!(
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status" FROM rooms WHERE code = $1"#,
         room
     )
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?
-    .board
-    .unwrap()
-    .into();
+    .ok_or(Error::RoomNotFound)?;
+    let rules = get_rules(room, pool).await?;
+    let game_over = room_details.stat == Status::GameOver;
 
-    let game_over = player_board.is_game_over() || opponent_board.is_game_over();
-
-    let player_board: Vec<String> = player_board.mark_redundant().into();
-
-    let opponent_board: Vec<String> = opponent_board.mark_redundant().into();
-    let opponent_board: Vec<String> = opponent_board
-        .into_iter()
-        .map(|row| {
-            row.chars()
-                .map(|x| if x == 's' { 'e' } else { x })
-                .collect()
-        })
-        .collect::<Vec<_>>();
+    let players = [room_details.player1_id, room_details.player2_id];
+    let mut boards = [vec![], vec![]];
+    for (board, player) in boards.iter_mut().zip(players.iter()) {
+        let stored = match player {
+            Some(id) => sqlx::query!(r"SELECT board FROM players WHERE id = $1", id)
+                .fetch_optional(pool)
+                .await?
+                .and_then(|player| player.board),
+            None => None,
+        };
+        *board = stored
+            .map(Board::from)
+            .unwrap_or_else(|| Board::new(rules.height, rules.width))
+            .mark_redundant(&rules.fleet)
+            .view(game_over);
+    }
 
-    Ok((turn, player_board, opponent_board, game_over))
+    let turn = match room_details.stat {
+        Status::P1Turn => players[0].clone(),
+        Status::P2Turn => players[1].clone(),
+        _ => None,
+    };
+    Ok(SpectatorState {
+        players,
+        boards,
+        turn,
+        game_over,
+    })
 }
 
-pub async fn start(sid: Sid, code: String, pool: &sqlx::PgPool) -> Result<()> {
+/// Starts the game of a full room with `starter` to shoot first.
+pub async fn start(starter: &str, code: String, pool: &sqlx::PgPool) -> Result<()> {
     let room = sqlx::query!(
         r"SELECT player1_id, player2_id FROM rooms WHERE code = $1",
         code
@@ -246,110 +1042,448 @@ pub async fn start(sid: Sid, code: String, pool: &sqlx::PgPool) -> Result<()> {
         return Err(Error::RoomNotFull); // room not full
     };
 
-    let status = if sid.as_str() == player1 {
+    let status = if starter == player1 {
         Status::P1Turn
-    } else if sid.as_str() == player2 {
+    } else if starter == player2 {
         Status::P2Turn
     } else {
         return Err(Error::NotInRoom); // not in room
     };
 
+    // every start is a new game in the log, with the fleets as placed
     sqlx::query!(
-        r"UPDATE rooms SET stat = $1 WHERE code = $2",
+        r"WITH game AS (INSERT INTO games (room_code, player1_id, player2_id, board1, board2, starter, account1_id, account2_id) SELECT $2, p1.id, p2.id, p1.board, p2.board, $5, (SELECT account_id FROM logins WHERE player_id = p1.id), (SELECT account_id FROM logins WHERE player_id = p2.id) FROM players p1, players p2 WHERE p1.id = $3 AND p2.id = $4 RETURNING id) UPDATE rooms SET stat = $1, game_id = (SELECT id FROM game), clock1 = clock * 1000, clock2 = clock * 1000, move_started = NOW() WHERE code = $2",
         status as Status,
-        code
+        code,
+        player1,
+        player2,
+        starter
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn attack(
-    sid: Sid,
+/// Stops the clock of the player who just moved, adding the increment, and starts the next move.
+async fn charge_clock(conn: &mut sqlx::PgConnection, code: &str, sid: &str) -> Result<()> {
+    sqlx::query!(
+        r"WITH elapsed AS (SELECT (EXTRACT(EPOCH FROM NOW() - move_started) * 1000)::BIGINT AS ms FROM rooms WHERE code = $1) UPDATE rooms SET clock1 = CASE WHEN player1_id = $2 THEN clock1 - LEAST(elapsed.ms, clock1) + increment * 1000 ELSE clock1 END, clock2 = CASE WHEN player2_id = $2 THEN clock2 - LEAST(elapsed.ms, clock2) + increment * 1000 ELSE clock2 END, move_started = NOW() FROM elapsed WHERE code = $1",
+        code,
+        sid
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Appends a shot to the log of the room's current game.
+async fn log_move(
+    conn: &mut sqlx::PgConnection,
+    code: &str,
+    shooter: &str,
     (i, j): (usize, usize),
-    pool: &sqlx::PgPool,
-) -> Result<(bool, Option<[(usize, usize); 2]>, bool)> {
-    let player = sqlx::query!(r"SELECT room_code FROM players WHERE id = $1", sid.as_str())
-        .fetch_one(pool)
+    hit: bool,
+    sunk: Option<&Sunk>,
+) -> Result<()> {
+    sqlx::query!(
+        r"INSERT INTO moves (game_id, move_number, shooter, i, j, hit, sunk) SELECT game_id, (SELECT COUNT(*) FROM moves WHERE moves.game_id = rooms.game_id)::INT + 1, $2, $3, $4, $5, $6 FROM rooms WHERE code = $1 AND game_id IS NOT NULL",
+        code,
+        shooter,
+        i as i16,
+        j as i16,
+        hit,
+        sunk.map(|sunk| sunk.name)
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Ends the room's current game, however it ended, and counts it towards the series and the
+/// ratings, stats and achievements of the players.
+async fn finish_game(conn: &mut sqlx::PgConnection, code: &str, winner: &str) -> Result<()> {
+    sqlx::query!(
+        r#"UPDATE rooms SET stat = $1 WHERE code = $2"#,
+        Status::GameOver as Status,
+        code
+    )
+    .execute(&mut *conn)
+    .await?;
+    let game = sqlx::query_scalar!(
+        r"UPDATE games SET winner = $2, ended_at = NOW() WHERE id = (SELECT game_id FROM rooms WHERE code = $1) RETURNING id",
+        code,
+        winner
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(game) = game {
+        if counts_for_accounts(conn, game).await? {
+            if is_rated(conn, code).await? {
+                rate_game(conn, game).await?;
+            }
+            record_game(conn, game).await?;
+            unlock_achievements(conn, game).await?;
+        }
+    }
+    sqlx::query!(
+        r"UPDATE rooms SET score1 = score1 + (player1_id = $2)::INT, score2 = score2 + (player2_id = $2)::INT WHERE code = $1",
+        code,
+        winner
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Whether the game counts towards the ratings, stats and achievements of the accounts behind it:
+/// someone played signed in, the two sides are not the same person, and nobody took over a seat
+/// along the way.
+async fn counts_for_accounts(conn: &mut sqlx::PgConnection, game: i32) -> Result<bool> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT account1_id IS DISTINCT FROM account2_id AND NOT EXISTS (SELECT 1 FROM substitutions WHERE game_id = $1) AS "counts!" FROM games WHERE id = $1"#,
+        game
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// Whether the game in the room moves ratings: players paired by the queue on the standard board.
+async fn is_rated(conn: &mut sqlx::PgConnection, code: &str) -> Result<bool> {
+    let matchmade = sqlx::query_scalar!(r"SELECT matchmade FROM rooms WHERE code = $1", code)
+        .fetch_one(&mut *conn)
         .await?;
+    let rules = read_rules(code, &mut *conn).await?;
+    Ok(matchmade && rules.is_standard())
+}
 
+/// Ends the game in progress in the player's room with the opponent as the winner,
+/// returning the room and the winner.
+pub async fn forfeit(sid: &str, pool: &sqlx::PgPool) -> Result<(String, String)> {
+    let code = room_if_player_exists(sid, pool)
+        .await?
+        .ok_or(Error::NotInRoom)?;
+    let mut txn = pool.begin().await?;
     let room = sqlx::query!(
-        r#"SELECT stat AS "stat: Status", player1_id, player2_id FROM rooms WHERE code = $1"#,
-        player.room_code
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status" FROM rooms WHERE code = $1 FOR UPDATE"#,
+        code
+    )
+    .fetch_one(&mut *txn)
+    .await?;
+    if !matches!(room.stat, Status::P1Turn | Status::P2Turn) {
+        return Err(Error::NoGameInProgress);
+    }
+    let winner = match (room.player1_id, room.player2_id) {
+        (Some(p1), Some(p2)) if p1 == sid => p2,
+        (Some(p1), Some(p2)) if p2 == sid => p1,
+        _ => return Err(Error::NotInRoom),
+    };
+    finish_game(&mut txn, &code, &winner).await?;
+    txn.commit().await?;
+    Ok((code, winner))
+}
+
+/// Asks the other player of a finished game for another one in the same room.
+pub async fn request_rematch(sid: &str, pool: &sqlx::PgPool) -> Result<String> {
+    let code = room_if_player_exists(sid, pool)
+        .await?
+        .ok_or(Error::NotInRoom)?;
+    let room = sqlx::query!(
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status" FROM rooms WHERE code = $1"#,
+        code
     )
     .fetch_one(pool)
     .await?;
+    if room.stat != Status::GameOver {
+        return Err(Error::GameNotOver);
+    }
+    if room.player1_id.is_none() || room.player2_id.is_none() {
+        return Err(Error::RoomNotFull);
+    }
+    sqlx::query!(r"UPDATE rooms SET rematch = $1 WHERE code = $2", sid, code)
+        .execute(pool)
+        .await?;
+    Ok(code)
+}
 
-    let (_, other, to_status) = match (room.player1_id, room.player2_id) {
-        (Some(p1), Some(p2)) if p1 == sid.as_str() && room.stat == Status::P1Turn => {
-            (p1, p2, Status::P2Turn)
-        }
-        (Some(p1), Some(p2)) if p2 == sid.as_str() && room.stat == Status::P2Turn => {
-            (p2, p1, Status::P1Turn)
+/// Accepts the rematch the other player asked for, returning the room and who starts.
+/// Players take turns to start, and a series that has been decided starts over.
+pub async fn accept_rematch(sid: &str, pool: &sqlx::PgPool) -> Result<(String, String)> {
+    let code = room_if_player_exists(sid, pool)
+        .await?
+        .ok_or(Error::NotInRoom)?;
+    let room = sqlx::query!(
+        r#"SELECT player1_id, player2_id, stat AS "stat: Status", rematch, (SELECT starter = player1_id FROM games WHERE id = game_id) AS first_started FROM rooms WHERE code = $1"#,
+        code
+    )
+    .fetch_one(pool)
+    .await?;
+    if room.stat != Status::GameOver {
+        return Err(Error::GameNotOver);
+    }
+    if room.rematch.is_none() || room.rematch.as_deref() == Some(sid) {
+        return Err(Error::NoRematchRequest);
+    }
+    let (Some(player1), Some(player2)) = (room.player1_id, room.player2_id) else {
+        return Err(Error::RoomNotFull);
+    };
+    // by seat, the player who started the last game may have been substituted since
+    let starter = if room.first_started == Some(true) {
+        player2
+    } else {
+        player1
+    };
+    let decided = get_score(&code, pool).await?.is_decided();
+    sqlx::query!(
+        r"UPDATE rooms SET stat = $1, rematch = NULL, score1 = CASE WHEN $2 THEN 0 ELSE score1 END, score2 = CASE WHEN $2 THEN 0 ELSE score2 END WHERE code = $3",
+        Status::Waiting as Status,
+        decided,
+        code
+    )
+    .execute(pool)
+    .await?;
+    Ok((code, starter))
+}
+
+/// The game with the given id, or the current game of the player's room.
+pub async fn replay(sid: &str, game: Option<i32>, pool: &sqlx::PgPool) -> Result<Replay> {
+    let id = match game {
+        Some(id) => id,
+        None => {
+            let room = get_room(sid, pool).await?.ok_or(Error::NotInRoom)?;
+            sqlx::query!(r"SELECT game_id FROM rooms WHERE code = $1", room)
+                .fetch_one(pool)
+                .await?
+                .game_id
+                .ok_or(Error::GameNotFound)?
         }
+    };
+    get_replay(id, sid, pool).await
+}
+
+/// The replay of a game as seen by the viewer. Finished games are public, a game in progress
+/// is only replayed to the players seated in its room.
+pub async fn get_replay(id: i32, viewer: &str, pool: &sqlx::PgPool) -> Result<Replay> {
+    let game = sqlx::query!(
+        r#"SELECT room_code, player1_id, player2_id, board1, board2, winner, (EXTRACT(EPOCH FROM started_at) * 1000)::BIGINT AS "started_at!", (EXTRACT(EPOCH FROM ended_at) * 1000)::BIGINT AS ended_at FROM games WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::GameNotFound)?;
+    let seated = room_if_player_exists(viewer, pool).await?;
+    if game.ended_at.is_none() && seated.as_deref() != Some(game.room_code.as_str()) {
+        return Err(Error::NotInRoom);
+    }
+    let moves = sqlx::query!(
+        r#"SELECT move_number, shooter, i, j, hit, sunk, (EXTRACT(EPOCH FROM time) * 1000)::BIGINT AS "time!" FROM moves WHERE game_id = $1 ORDER BY move_number"#,
+        id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|m| Move {
+        number: m.move_number,
+        shooter: m.shooter,
+        at: (m.i as usize, m.j as usize),
+        hit: m.hit,
+        sunk: m.sunk,
+        time: m.time,
+    })
+    .collect();
+
+    let boards = game
+        .ended_at
+        .map(|_| [game.board1, game.board2].map(|board| Board::from(board).view(true)));
+    Ok(Replay {
+        game: id,
+        room: game.room_code,
+        players: [game.player1_id, game.player2_id],
+        substitutions: substitutions(id, pool).await?,
+        boards,
+        winner: game.winner,
+        started_at: game.started_at,
+        ended_at: game.ended_at,
+        moves,
+    })
+}
+
+/// Finds the room of the player whose turn it is, their opponent and the status that passes the turn.
+/// The room stays locked until the transaction ends, so moves are resolved one at a time.
+async fn turn_of(sid: &str, conn: &mut sqlx::PgConnection) -> Result<(String, String, Status)> {
+    let player = sqlx::query!(r"SELECT room_code FROM players WHERE id = $1", sid)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let room = sqlx::query!(
+        r#"SELECT stat AS "stat: Status", player1_id, player2_id FROM rooms WHERE code = $1 FOR UPDATE"#,
+        player.room_code
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let (other, to_status) = match (room.player1_id, room.player2_id, room.stat) {
+        (Some(p1), Some(p2), Status::P1Turn) if p1 == sid => (p2, Status::P2Turn),
+        (Some(p1), Some(p2), Status::P2Turn) if p2 == sid => (p1, Status::P1Turn),
+        (Some(_), Some(_), Status::P1Turn | Status::P2Turn) => return Err(Error::NotYourTurn),
+        (Some(_), Some(_), _) => return Err(Error::NoGameInProgress),
         _ => return Err(Error::RoomNotFull), // room not full
     };
+    Ok((player.room_code, other, to_status))
+}
+
+/// Fires a single shot, returns the hit, the ship it sank, whether the game is over
+/// and whose turn it is next.
+pub async fn attack(
+    sid: &str,
+    (i, j): (usize, usize),
+    pool: &sqlx::PgPool,
+) -> Result<(bool, Option<Sunk>, bool, String)> {
+    let mut txn = pool.begin().await?;
+    let (code, other, to_status) = turn_of(sid, &mut txn).await?;
+    let rules = get_rules(&code, pool).await?;
+    if rules.mode != Mode::Classic {
+        return Err(Error::WrongMode(rules.mode));
+    }
 
     let mut board: Board = sqlx::query!(r"SELECT board FROM players WHERE id = $1", other)
-        .fetch_one(pool)
+        .fetch_one(&mut *txn)
         .await?
         .board
         .unwrap()
         .into();
 
-    let hit = match board[i][j] {
-        's' => true,
-        'e' => false,
-        _ => return Err(Error::InvalidMove),
-    };
-    board[i][j] = if hit { 'h' } else { 'm' };
+    let hit = board.fire((i, j)).ok_or(Error::InvalidMove)?;
+    let sunk = board.has_sunk((i, j), &rules.fleet);
+    let keep_turn = rules.turn_policy.keeps_turn(hit, sunk.is_some());
+    log_move(&mut txn, &code, sid, (i, j), hit, sunk.as_ref()).await?;
 
-    let mut txn = pool.begin().await?;
     sqlx::query!(
         r#"UPDATE players SET board[$1] = $2 WHERE id = $3"#,
         i as i32 + 1,
-        board[i].iter().collect::<String>(),
+        board.encode_row(i),
         other
     )
     .execute(&mut *txn)
     .await?;
 
-    if !hit {
+    if !keep_turn {
         sqlx::query!(
             r#"UPDATE rooms SET stat = $1 WHERE code = $2"#,
             to_status as Status,
-            player.room_code
+            code
         )
         .execute(&mut *txn)
         .await?;
     }
-    let game_over = board.is_game_over();
+    charge_clock(&mut txn, &code, sid).await?;
+    let game_over = board.is_game_over(&rules.fleet);
     if game_over {
-        sqlx::query!(
-            r#"UPDATE rooms SET stat = $1 WHERE code = $2"#,
-            Status::GameOver as Status,
-            player.room_code
-        )
-        .execute(&mut *txn)
-        .await?;
+        finish_game(&mut txn, &code, sid).await?;
     }
 
     txn.commit().await?;
-    Ok((
-        hit,
-        if hit { board.has_sunk((i, j)) } else { None },
-        game_over,
-    ))
+    let next = if keep_turn { sid.to_string() } else { other };
+    Ok((hit, sunk, game_over, next))
+}
+
+/// Fires a whole volley, one shot for each of the shooter's ships still afloat.
+/// The turn always passes after a volley, whatever the turn policy.
+pub async fn salvo(
+    sid: &str,
+    targets: &[(usize, usize)],
+    pool: &sqlx::PgPool,
+) -> Result<(Vec<Shot>, bool, String)> {
+    let mut txn = pool.begin().await?;
+    let (code, other, to_status) = turn_of(sid, &mut txn).await?;
+    let rules = get_rules(&code, pool).await?;
+    if rules.mode != Mode::Salvo {
+        return Err(Error::WrongMode(rules.mode));
+    }
+
+    let own: Board = sqlx::query!(r"SELECT board FROM players WHERE id = $1", sid)
+        .fetch_one(&mut *txn)
+        .await?
+        .board
+        .unwrap()
+        .into();
+    let mut board: Board = sqlx::query!(r"SELECT board FROM players WHERE id = $1", other)
+        .fetch_one(&mut *txn)
+        .await?
+        .board
+        .unwrap()
+        .into();
+
+    if targets.len() != own.ships_afloat().min(board.unexplored()) {
+        return Err(Error::InvalidMove);
+    }
+    let mut shots = Vec::with_capacity(targets.len());
+    for &at in targets {
+        // firing twice at the same cell of a volley is rejected here as well
+        let hit = board.fire(at).ok_or(Error::InvalidMove)?;
+        let sunk = board.has_sunk(at, &rules.fleet);
+        log_move(&mut txn, &code, sid, at, hit, sunk.as_ref()).await?;
+        shots.push(Shot { at, hit, sunk });
+    }
+    let game_over = board.is_game_over(&rules.fleet);
+    let board: Vec<String> = board.into();
+
+    sqlx::query!(
+        r#"UPDATE players SET board = $1 WHERE id = $2"#,
+        &board,
+        other
+    )
+    .execute(&mut *txn)
+    .await?;
+    sqlx::query!(
+        r#"UPDATE rooms SET stat = $1 WHERE code = $2"#,
+        to_status as Status,
+        code
+    )
+    .execute(&mut *txn)
+    .await?;
+    charge_clock(&mut txn, &code, sid).await?;
+    if game_over {
+        finish_game(&mut txn, &code, sid).await?;
+    }
+    txn.commit().await?;
+    Ok((shots, game_over, other))
 }
 
-pub async fn update_sid(oldsid: &str, newsid: &str, pool: &sqlx::PgPool) -> Result<()> {
+/// Gives the seat of a player who left to another, fleet and all, making them the player from
+/// then on in the room and a substitute in its current game.
+pub async fn take_seat(code: &str, from: &str, to: &str, pool: &sqlx::PgPool) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    sqlx::query!(r"DELETE FROM players WHERE id = $1", to)
+        .execute(&mut *txn)
+        .await?;
     sqlx::query!(
-        r"UPDATE players SET id = $1, abandoned = FALSE WHERE id = $2",
-        newsid,
-        oldsid
+        r"INSERT INTO players (id, room_code, board) SELECT $2, room_code, board FROM players WHERE id = $1",
+        from,
+        to
     )
-    .execute(pool)
+    .execute(&mut *txn)
     .await?;
+    // the game in progress stays with the player who started in the seat
+    sqlx::query!(
+        r"INSERT INTO substitutions (game_id, seat, substitute, from_move) SELECT g.id, CASE WHEN r.player1_id = $1 THEN 1 ELSE 2 END, $2, (SELECT COUNT(*) FROM moves m WHERE m.game_id = g.id)::INT + 1 FROM rooms r JOIN games g ON g.id = r.game_id WHERE r.code = $3 AND g.ended_at IS NULL",
+        from,
+        to,
+        code
+    )
+    .execute(&mut *txn)
+    .await?;
+    sqlx::query!(
+        r"UPDATE rooms SET player1_id = CASE WHEN player1_id = $1 THEN $2 ELSE player1_id END, player2_id = CASE WHEN player2_id = $1 THEN $2 ELSE player2_id END, rematch = CASE WHEN rematch = $1 THEN $2 ELSE rematch END WHERE code = $3",
+        from,
+        to,
+        code
+    )
+    .execute(&mut *txn)
+    .await?;
+    sqlx::query!(r"DELETE FROM players WHERE id = $1", from)
+        .execute(&mut *txn)
+        .await?;
+    txn.commit().await?;
     Ok(())
 }
 
@@ -367,6 +1501,14 @@ pub async fn to_delete_sid(sid: &str, pool: &sqlx::PgPool) -> Result<()> {
     Ok(())
 }
 
+/// Clears the mark of a player who came back before being removed.
+pub async fn restore_sid(sid: &str, pool: &sqlx::PgPool) -> Result<()> {
+    sqlx::query!(r"UPDATE players SET abandoned = FALSE WHERE id = $1", sid)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 pub async fn in_delete_sid(sid: &str, pool: &sqlx::PgPool) -> Result<bool> {
     Ok(
         sqlx::query!(r"SELECT abandoned FROM players WHERE id = $1", sid)
@@ -375,3 +1517,195 @@ pub async fn in_delete_sid(sid: &str, pool: &sqlx::PgPool) -> Result<bool> {
             .abandoned,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bonus_on_hit_keeps_the_turn_on_any_hit() {
+        let policy = TurnPolicy::BonusOnHit;
+        assert!(policy.keeps_turn(true, false));
+        assert!(policy.keeps_turn(true, true));
+        assert!(!policy.keeps_turn(false, false));
+    }
+
+    #[test]
+    fn alternate_always_passes_the_turn() {
+        let policy = TurnPolicy::Alternate;
+        assert!(!policy.keeps_turn(true, false));
+        assert!(!policy.keeps_turn(true, true));
+        assert!(!policy.keeps_turn(false, false));
+    }
+
+    #[test]
+    fn bonus_on_sink_keeps_the_turn_only_on_a_sink() {
+        let policy = TurnPolicy::BonusOnSink;
+        assert!(!policy.keeps_turn(true, false));
+        assert!(policy.keeps_turn(true, true));
+        assert!(!policy.keeps_turn(false, false));
+    }
+
+    fn score(wins: [usize; 2], best_of: usize) -> Score {
+        Score {
+            players: [Some("p1".to_string()), Some("p2".to_string())],
+            wins,
+            best_of,
+        }
+    }
+
+    #[test]
+    fn a_single_game_decides_a_best_of_one() {
+        assert!(!score([0, 0], 1).is_decided());
+        assert!(score([1, 0], 1).is_decided());
+        assert!(score([0, 1], 1).is_decided());
+    }
+
+    #[test]
+    fn a_series_is_decided_by_a_majority_of_its_games() {
+        assert!(!score([0, 0], 5).is_decided());
+        assert!(!score([2, 0], 5).is_decided());
+        assert!(!score([2, 2], 5).is_decided());
+        assert!(score([3, 0], 5).is_decided());
+        assert!(score([2, 3], 5).is_decided());
+        assert!(!score([3, 3], 7).is_decided());
+        assert!(score([4, 3], 7).is_decided());
+    }
+
+    #[test]
+    fn best_of_must_be_odd_and_bounded() {
+        let rules = |best_of| Rules {
+            best_of,
+            ..Rules::default()
+        };
+        assert!(rules(1).validate().is_ok());
+        assert!(rules(MAX_BEST_OF).validate().is_ok());
+        assert!(rules(0).validate().is_err());
+        assert!(rules(4).validate().is_err());
+        assert!(rules(MAX_BEST_OF + 2).validate().is_err());
+    }
+
+    fn seats() -> [Option<String>; 2] {
+        [Some("a".repeat(16)), Some("b".repeat(16))]
+    }
+
+    fn boards() -> [Option<Vec<String>>; 2] {
+        [Some(vec!["AA..".into()]), Some(vec!["a*..".into()])]
+    }
+
+    #[test]
+    fn game_state_waits_for_an_opponent() {
+        let alone = [Some("a".repeat(16)), None];
+        let state = GameState::of(
+            &"a".repeat(16),
+            alone,
+            [None, None],
+            Status::Waiting,
+            None,
+            &[2],
+        );
+        assert!(matches!(state, Err(Error::NotInRoom)));
+        let stranger = GameState::of("c", seats(), boards(), Status::P1Turn, None, &[2]);
+        assert!(matches!(stranger, Err(Error::NotInRoom)));
+    }
+
+    #[test]
+    fn game_state_waits_for_both_fleets() {
+        let placing = [Some(vec!["AA..".into()]), None];
+        let state = GameState::of(
+            &"a".repeat(16),
+            seats(),
+            placing,
+            Status::Waiting,
+            None,
+            &[2],
+        );
+        assert!(matches!(state, Err(Error::NoGameInProgress)));
+    }
+
+    #[test]
+    fn game_state_is_seen_from_the_players_seat() {
+        let first = GameState::of(
+            &"a".repeat(16),
+            seats(),
+            boards(),
+            Status::P2Turn,
+            None,
+            &[2],
+        );
+        let first = first.unwrap();
+        assert!(!first.turn);
+        assert_eq!(first.player, ["ssee"]);
+        assert_eq!(first.opponent, ["hmee"]);
+        let second = GameState::of(
+            &"b".repeat(16),
+            seats(),
+            boards(),
+            Status::P2Turn,
+            None,
+            &[2],
+        );
+        let second = second.unwrap();
+        assert!(second.turn);
+        assert_eq!(second.player, ["hmee"]);
+        assert_eq!(second.opponent, ["eeee"]);
+        assert_eq!(second.winner, None);
+    }
+
+    fn clock(
+        turn: Option<usize>,
+        remaining: [Option<i64>; 2],
+        move_limit: Option<i32>,
+        elapsed: i64,
+    ) -> Clock {
+        Clock::running(
+            [Some("p1".to_string()), Some("p2".to_string())],
+            turn,
+            remaining,
+            move_limit,
+            elapsed,
+        )
+    }
+
+    #[test]
+    fn clocks_expire_with_the_sooner_of_the_move_limit_and_the_clock() {
+        let limited = clock(Some(0), [Some(60_000), Some(90_000)], Some(30), 10_000);
+        assert_eq!(limited.remaining, [Some(50_000), Some(90_000)]);
+        assert_eq!(limited.move_left, Some(20_000));
+        assert_eq!(limited.expires_in(), Some(20_000));
+        assert_eq!(limited.player(), Some("p1"));
+
+        let short_clock = clock(Some(1), [Some(60_000), Some(15_000)], Some(30), 10_000);
+        assert_eq!(short_clock.remaining, [Some(60_000), Some(5_000)]);
+        assert_eq!(short_clock.expires_in(), Some(5_000));
+        assert_eq!(short_clock.player(), Some("p2"));
+
+        assert_eq!(
+            clock(Some(0), [None, None], Some(30), 1_000).expires_in(),
+            Some(29_000)
+        );
+        assert_eq!(
+            clock(Some(0), [Some(9_000), Some(9_000)], None, 1_000).expires_in(),
+            Some(8_000)
+        );
+    }
+
+    #[test]
+    fn clocks_stop_at_zero_once_expired() {
+        let expired = clock(Some(0), [Some(60_000), Some(60_000)], Some(30), 45_000);
+        assert_eq!(expired.move_left, Some(0));
+        assert_eq!(expired.expires_in(), Some(0));
+        let flagged = clock(Some(1), [Some(60_000), Some(5_000)], None, 8_000);
+        assert_eq!(flagged.remaining, [Some(60_000), Some(0)]);
+        assert_eq!(flagged.expires_in(), Some(0));
+    }
+
+    #[test]
+    fn clocks_only_run_during_a_game() {
+        let between = clock(None, [Some(60_000), Some(50_000)], Some(30), 45_000);
+        assert_eq!(between.remaining, [Some(60_000), Some(50_000)]);
+        assert_eq!(between.move_left, None);
+        assert_eq!(between.expires_in(), None);
+        assert_eq!(between.player(), None);
+    }
+}
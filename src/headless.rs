@@ -0,0 +1,431 @@
+//! Line-delimited JSON over TCP for programs that play without the web client.
+//! The messages are documented in the README, bump `PROTOCOL_VERSION` when they change.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use socketioxide::socket::Sid;
+use sqlx::PgPool;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use crate::events::{Attacked, Volley};
+use crate::game::{
+    add_board, add_room, attack, delete_sid, forfeit, get_game_state, get_room, join_room, salvo,
+    start, Access, Credentials, Error, GameState, Rules,
+};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Hello {
+        version: u32,
+        name: String,
+    },
+    Queue {
+        #[serde(default)]
+        rules: Rules,
+    },
+    Board {
+        layout: Vec<String>,
+    },
+    Attack {
+        at: (usize, usize),
+    },
+    Salvo {
+        targets: Vec<(usize, usize)>,
+    },
+}
+
+/// Requests may carry an id, which is echoed back on the reply.
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Welcome {
+        version: u32,
+        id: String,
+    },
+    Queued,
+    Matched {
+        room: String,
+        opponent: String,
+        rules: Rules,
+    },
+    /// The board is expected next.
+    Upload {
+        rules: Rules,
+    },
+    Accepted,
+    Attacked(Attacked),
+    Volley(Volley),
+    State(GameState),
+    OpponentLeft,
+    Error {
+        code: &'static str,
+        message: String,
+    },
+}
+
+impl From<Error> for Reply {
+    fn from(e: Error) -> Self {
+        Reply::Error {
+            code: e.code(),
+            message: e.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Message {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(flatten)]
+    reply: Reply,
+}
+
+type Sender = mpsc::UnboundedSender<String>;
+
+fn send(to: &Sender, id: Option<u64>, reply: Reply) {
+    let mut line = serde_json::to_string(&Message { id, reply }).unwrap();
+    line.push('\n');
+    // the connection is gone if this fails, which it cleans up itself
+    let _ = to.send(line);
+}
+
+#[derive(Debug, Clone)]
+struct Bot {
+    id: Sid,
+    name: String,
+}
+
+/// Bots connected to this server and the games they are in.
+#[derive(Default)]
+struct Arena {
+    senders: Mutex<HashMap<Sid, Sender>>,
+    /// Bots waiting for an opponent, with the rules they asked for.
+    queue: Mutex<Vec<(Bot, Rules)>>,
+    opponents: Mutex<HashMap<Sid, Sid>>,
+    /// Per room, the player who starts and the players that have not placed their fleet yet.
+    placing: Mutex<HashMap<String, (Sid, Vec<Sid>)>>,
+}
+
+impl Arena {
+    fn send(&self, to: Sid, reply: Reply) {
+        if let Some(sender) = self.senders.lock().unwrap().get(&to) {
+            send(sender, None, reply);
+        }
+    }
+
+    fn opponent(&self, of: Sid) -> Option<Sid> {
+        self.opponents.lock().unwrap().get(&of).copied()
+    }
+}
+
+pub async fn serve(listener: TcpListener, pool: PgPool) {
+    let arena = Arc::new(Arena::default());
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tracing::info!("Bot connected from {}", addr);
+                tokio::spawn(connection(stream, arena.clone(), pool.clone()));
+            }
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    }
+}
+
+async fn connection(stream: TcpStream, arena: Arc<Arena>, pool: PgPool) {
+    let (read, mut write) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let writer = tokio::spawn(async move {
+        while let Some(line) = rx.recv().await {
+            if write.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut bot: Option<Bot> = None;
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Envelope { id, request } = match serde_json::from_str(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                let (code, message) = ("bad_request", e.to_string());
+                send(&tx, None, Reply::Error { code, message });
+                continue;
+            }
+        };
+        // the first reply answers the request, the rest follow it
+        let replies = match (request, &bot) {
+            (Request::Hello { version, name }, None) => {
+                if version != PROTOCOL_VERSION {
+                    let code = "unsupported_version";
+                    let message = format!("Server speaks version {PROTOCOL_VERSION}");
+                    vec![Reply::Error { code, message }]
+                } else {
+                    let me = Bot {
+                        id: Sid::new(),
+                        name,
+                    };
+                    arena.senders.lock().unwrap().insert(me.id, tx.clone());
+                    let id = me.id.to_string();
+                    bot = Some(me);
+                    vec![Reply::Welcome {
+                        version: PROTOCOL_VERSION,
+                        id,
+                    }]
+                }
+            }
+            (Request::Hello { .. }, Some(_)) => vec![Reply::Error {
+                code: "already_registered",
+                message: "Hello was already sent".into(),
+            }],
+            (_, None) => vec![Reply::Error {
+                code: "not_registered",
+                message: "Send hello first".into(),
+            }],
+            (Request::Queue { rules }, Some(me)) => queue(me, rules, &arena, &pool).await,
+            (Request::Board { layout }, Some(me)) => place(me, layout, &arena, &pool).await,
+            (Request::Attack { at }, Some(me)) => fire(me, at, &arena, &pool).await,
+            (Request::Salvo { targets }, Some(me)) => volley(me, targets, &arena, &pool).await,
+        };
+        for (k, reply) in replies.into_iter().enumerate() {
+            send(&tx, if k == 0 { id } else { None }, reply);
+        }
+    }
+
+    if let Some(me) = bot {
+        tracing::info!("Bot disconnected: {:?}", me.id);
+        leave(me.id, &arena);
+        // a bot cannot come back, so its game is lost straight away
+        match forfeit(me.id.as_str(), &pool).await {
+            Ok(_) | Err(Error::NoGameInProgress | Error::NotInRoom) => {}
+            Err(e) => tracing::error!("{:?}", e),
+        }
+        if let Err(e) = delete_sid(me.id.as_str(), &pool).await {
+            tracing::error!("{:?}", e);
+        }
+    }
+    writer.abort();
+}
+
+fn leave(me: Sid, arena: &Arena) {
+    arena.senders.lock().unwrap().remove(&me);
+    arena.queue.lock().unwrap().retain(|(bot, _)| bot.id != me);
+    let opponent = arena.opponents.lock().unwrap().remove(&me);
+    if let Some(opponent) = opponent {
+        arena.opponents.lock().unwrap().remove(&opponent);
+        arena.send(opponent, Reply::OpponentLeft);
+    }
+}
+
+/// Pairs the bot with the first one waiting for the same rules, or puts it in the queue.
+async fn queue(me: &Bot, rules: Rules, arena: &Arena, pool: &PgPool) -> Vec<Reply> {
+    // the computer and time controls are only for web rooms
+    if rules.bot.is_some() || rules.time_control.is_some() {
+        return vec![Error::InvalidRules.into()];
+    }
+    if rules.hints {
+        return vec![Error::HintsDisabled.into()];
+    }
+    if let Err(e) = rules.validate() {
+        return vec![e.into()];
+    }
+    let waiting = {
+        let mut queue = arena.queue.lock().unwrap();
+        queue.retain(|(bot, _)| bot.id != me.id);
+        match queue.iter().position(|(_, wanted)| *wanted == rules) {
+            Some(k) => Some(queue.remove(k).0),
+            None => {
+                queue.push((me.clone(), rules.clone()));
+                None
+            }
+        }
+    };
+    let Some(other) = waiting else {
+        return vec![Reply::Queued];
+    };
+
+    let room = match add_room(other.id.as_str(), &rules, &Access::default(), true, pool).await {
+        Ok(room) => room,
+        Err(e) => return requeue(other, rules, e, arena),
+    };
+    if let Err(e) = join_room(me.id.as_str(), room.clone(), &Credentials::default(), pool).await {
+        // the room was only for this match
+        if let Err(e) = delete_sid(other.id.as_str(), pool).await {
+            tracing::error!("{:?}", e);
+        }
+        return requeue(other, rules, e, arena);
+    }
+    tracing::info!("Bots matched in room: {:?}", room);
+    {
+        let mut opponents = arena.opponents.lock().unwrap();
+        opponents.insert(me.id, other.id);
+        opponents.insert(other.id, me.id);
+    }
+    // like in web rooms, the one who joined starts
+    arena
+        .placing
+        .lock()
+        .unwrap()
+        .insert(room.clone(), (me.id, vec![me.id, other.id]));
+
+    let matched = |opponent: &Bot| Reply::Matched {
+        room: room.clone(),
+        opponent: opponent.name.clone(),
+        rules: rules.clone(),
+    };
+    arena.send(other.id, matched(me));
+    arena.send(
+        other.id,
+        Reply::Upload {
+            rules: rules.clone(),
+        },
+    );
+    vec![matched(&other), Reply::Upload { rules }]
+}
+
+/// Puts the bot that was waiting back at the front of the queue when its match could not be
+/// set up, and fails the request of the one that found it.
+fn requeue(waiting: Bot, rules: Rules, error: Error, arena: &Arena) -> Vec<Reply> {
+    tracing::error!("{:?}", error);
+    // a bot that has left since is not waiting anymore
+    if arena.senders.lock().unwrap().contains_key(&waiting.id) {
+        arena.queue.lock().unwrap().insert(0, (waiting, rules));
+    }
+    vec![error.into()]
+}
+
+async fn place(me: &Bot, layout: Vec<String>, arena: &Arena, pool: &PgPool) -> Vec<Reply> {
+    let room = match get_room(me.id.as_str(), pool).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return vec![Error::NotInRoom.into()],
+        Err(e) => return vec![e.into()],
+    };
+    let is_placing = |placing: &HashMap<String, (Sid, Vec<Sid>)>| {
+        placing
+            .get(&room)
+            .is_some_and(|(_, left)| left.contains(&me.id))
+    };
+    if !is_placing(&arena.placing.lock().unwrap()) {
+        return vec![Error::InvalidMove.into()];
+    }
+    let layout = layout.iter().map(|row| row.chars().collect()).collect();
+    if let Err(e) = add_board(me.id.as_str(), layout, pool).await {
+        return vec![e.into()];
+    }
+
+    let starter = {
+        let mut placing = arena.placing.lock().unwrap();
+        let Some((starter, left)) = placing.get_mut(&room) else {
+            return vec![Reply::Accepted];
+        };
+        left.retain(|&id| id != me.id);
+        let starter = *starter;
+        if !left.is_empty() {
+            return vec![Reply::Accepted];
+        }
+        placing.remove(&room);
+        starter
+    };
+    if let Err(e) = start(starter.as_str(), room, pool).await {
+        return vec![e.into()];
+    }
+    tracing::info!("Bot game started");
+    let mut replies = vec![Reply::Accepted];
+    replies.extend(states(me.id, arena, pool).await);
+    replies
+}
+
+async fn fire(me: &Bot, at: (usize, usize), arena: &Arena, pool: &PgPool) -> Vec<Reply> {
+    let (hit, sunk, game_over, _) = match attack(me.id.as_str(), at, pool).await {
+        Ok(res) => res,
+        Err(e) => return vec![e.into()],
+    };
+    let attacked = |sunk| Attacked {
+        by: me.id.to_string(),
+        at,
+        hit,
+        sunk,
+        game_over,
+        clock: None,
+    };
+    if let Some(opponent) = arena.opponent(me.id) {
+        arena.send(opponent, Reply::Attacked(attacked(sunk.clone())));
+    }
+    let mut replies = vec![Reply::Attacked(attacked(sunk))];
+    replies.extend(states(me.id, arena, pool).await);
+    replies
+}
+
+async fn volley(
+    me: &Bot,
+    targets: Vec<(usize, usize)>,
+    arena: &Arena,
+    pool: &PgPool,
+) -> Vec<Reply> {
+    let (shots, game_over, _) = match salvo(me.id.as_str(), &targets, pool).await {
+        Ok(res) => res,
+        Err(e) => return vec![e.into()],
+    };
+    if let Some(opponent) = arena.opponent(me.id) {
+        let shots = shots.clone();
+        let by = me.id.to_string();
+        let volley = Volley {
+            by,
+            shots,
+            game_over,
+            clock: None,
+        };
+        arena.send(opponent, Reply::Volley(volley));
+    }
+    let mut replies = vec![Reply::Volley(Volley {
+        by: me.id.to_string(),
+        shots,
+        game_over,
+        clock: None,
+    })];
+    replies.extend(states(me.id, arena, pool).await);
+    replies
+}
+
+/// Sends the opponent the new state of the game and returns the mover's own, which goes out
+/// after the reply to the move.
+async fn states(me: Sid, arena: &Arena, pool: &PgPool) -> Option<Reply> {
+    let room = match get_room(me.as_str(), pool).await {
+        Ok(room) => room?,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return None;
+        }
+    };
+    if let Some(opponent) = arena.opponent(me) {
+        if let Some(state) = state(opponent, &room, pool).await {
+            arena.send(opponent, state);
+        }
+    }
+    state(me, &room, pool).await
+}
+
+async fn state(of: Sid, room: &str, pool: &PgPool) -> Option<Reply> {
+    match get_game_state(of.as_str(), room, pool).await {
+        Ok(state) => Some(Reply::State(state)),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            None
+        }
+    }
+}
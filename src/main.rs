@@ -1,24 +1,74 @@
+mod accounts;
+mod achievements;
+mod api;
 mod board;
+mod bot;
+mod chat;
+mod events;
 mod game;
+mod headless;
+mod matchmaking;
+mod openapi;
+mod ratings;
+mod session;
+mod stats;
+mod timers;
 
+use accounts::{account_of, display_names, login, logout, register, Account, Login, Registration};
+use achievements::{achievements_of, unlocked_in, Unlocked};
+use api::Api;
 use axum::Router;
-use board::Board;
+use board::{Invalid, Layout};
+use bot::{is_bot, next_shot, Difficulty};
+use chat::{recent_messages, save_message, Chat};
 use dotenv::dotenv;
-use futures_util::stream::StreamExt;
+use events::{
+    AchievementUnlocked, Achievements, Attacked, GameOver, Lobby, Restore, RoomUpdate, Spectating,
+    Volley,
+};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, StreamExt};
 use game::{
-    add_board, add_room, attack, delete_sid, get_game_state, get_room, join_room,
-    room_if_player_exists, start, to_delete_sid, update_sid, Error, ROOM_CODE_LENGTH,
+    accept_rematch, add_board, add_bot, add_room, attack, check_watch, create_invite, delete_sid,
+    forfeit, get_clock, get_game_state, get_lobby, get_room, get_rules, get_score,
+    get_spectator_state, hint, in_delete_sid, join_room, make_public, random_targets, replay,
+    request_rematch, restore_sid, room_if_player_exists, salvo, start, take_seat, to_delete_sid,
+    Access, Credentials, Error, Mode, OnTimeout, Reason, Rules, ROOM_CODE_LENGTH,
 };
+use matchmaking::{Found, Matchmaking, MATCH_TIMEOUT, RATING_WINDOW_STEP};
+use ratings::{leaderboard, rating_of, LeaderboardQuery};
 
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer};
+use session::{new_player_id, PlayerId, Session, Sessions};
 use socketioxide::{
-    extract::{Data, SocketRef, State},
-    SocketIo,
+    extract::{AckSender, Data, SocketRef, State},
+    operators::BroadcastOperators,
+    AckError, SocketIo,
 };
 use sqlx::PgPool;
+use stats::get_stats;
+use std::time::{Duration, Instant};
+use timers::Timers;
 use tokio::net::TcpListener;
+use tower_http::cors::CorsLayer;
 use tracing_subscriber::FmtSubscriber;
 
+const UPLOAD_ATTEMPTS: usize = 3;
+/// How long a player whose fleet was rejected has to place it again.
+const REPLACEMENT_TIME: Duration = Duration::from_secs(120);
+/// Spectators of a room are also in the room with this suffix, which players never join.
+const SPECTATORS_SUFFIX: &str = ":spectators";
+/// Sockets browsing the lobby are in this room, which is never a room code.
+const LOBBY: &str = "lobby";
+/// Where programs connect to play over the headless protocol.
+const HEADLESS_ADDR: &str = "0.0.0.0:3001";
+/// How long a disconnected player has to come back before forfeiting the game.
+const FORFEIT_GRACE: Duration = Duration::from_secs(60);
+/// Pause before each shot of the computer, so players can follow its moves.
+const BOT_DELAY: Duration = Duration::from_millis(800);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::subscriber::set_global_default(
@@ -29,13 +79,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = dotenv();
     let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = sqlx::postgres::PgPool::connect(&url).await?;
+    let key = match std::env::var("SESSION_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+        _ => {
+            tracing::warn!("SESSION_SECRET not set, sessions will not outlive the server");
+            rand::random::<[u8; 32]>().to_vec()
+        }
+    };
     sqlx::migrate!("./migrations").run(&pool).await?;
     sqlx::query("DELETE FROM players").execute(&pool).await?;
-    let (layer, io) = SocketIo::builder().with_state(pool).build_layer();
+    let headless = TcpListener::bind(HEADLESS_ADDR).await?;
+    tokio::spawn(headless::serve(headless, pool.clone()));
+    let sessions = Sessions::new(&key);
+    let (layer, io) = SocketIo::builder()
+        .with_state(pool.clone())
+        .with_state(Chat::default())
+        .with_state(Timers::default())
+        .with_state(Matchmaking::default())
+        .with_state(sessions.clone())
+        .build_layer();
 
     io.ns("/", on_connect);
 
-    let app = Router::new().layer(layer);
+    let api = Api {
+        pool,
+        sessions,
+        io: io.clone(),
+    };
+    let app = Router::new()
+        .nest("/api/v1", api::router(api))
+        .layer(CorsLayer::permissive())
+        .layer(layer);
 
     let listener = TcpListener::bind("0.0.0.0:3000").await?;
     println!("listening on {}", listener.local_addr()?);
@@ -45,47 +119,95 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 #[derive(Debug, Deserialize)]
 struct AuthPayload {
-    pub session: Option<String>,
+    /// Token from an earlier connection, to carry on as the same player.
+    pub token: Option<String>,
 }
 
-async fn on_connect(socket: SocketRef, Data(auth): Data<AuthPayload>, pool: State<PgPool>) {
-    tracing::info!("Connected: {:?}", socket.id);
-    tracing::info!("Connected: {:?}", auth.session);
+/// A payload whose fields all have defaults, which clients may leave out altogether.
+#[derive(Debug, Default)]
+struct OrDefault<T>(T);
 
-    if let Some(sid) = auth.session {
-        update_sid(&sid, socket.id.as_str(), &pool).await.unwrap();
-        let sid = socket.id.as_str();
-        if let Some(room) = room_if_player_exists(sid, &pool).await.unwrap() {
-            let data = get_game_state(sid, &room, &pool).await.unwrap();
-            socket
-                .emit(
-                    "restore",
-                    serde_json::json!({"turn": data.0, "player": data.1, "opponent": data.2, "gameover": data.3}),
-                )
-                .unwrap();
-            socket.join(room.clone()).unwrap();
-            emit_update_room(
-                &socket,
-                &room,
-                socket.within(room.clone()).sockets().unwrap().len(),
-            );
+impl<'de, T: DeserializeOwned + Default> Deserialize<'de> for OrDefault<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // an event sent without a payload arrives as an empty list of arguments
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Null => Ok(OrDefault(T::default())),
+            serde_json::Value::Array(args) if args.is_empty() => Ok(OrDefault(T::default())),
+            payload => serde_json::from_value(payload)
+                .map(OrDefault)
+                .map_err(D::Error::custom),
         }
     }
+}
+
+/// A room to create, as the `create` event and `POST /rooms` take it.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(transform = openapi::without_bot)]
+struct NewRoom {
+    #[serde(flatten)]
+    pub rules: Rules,
+    /// Lists the room in the lobby for anyone to join.
+    #[serde(default)]
+    pub public: bool,
+    #[serde(flatten)]
+    pub access: Access,
+}
 
+/// A room code alone, or with credentials for a restricted room.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JoinRequest {
+    Code(String),
+    WithCredentials {
+        room: String,
+        #[serde(flatten)]
+        credentials: Credentials,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ReplayRequest {
+    /// Defaults to the current game of the player's room.
+    pub game: Option<i32>,
+}
+
+async fn on_connect(
+    socket: SocketRef,
+    Data(auth): Data<AuthPayload>,
+    pool: State<PgPool>,
+    sessions: State<Sessions>,
+) {
+    // a token this server did not sign starts a new player
+    let player = auth
+        .token
+        .as_deref()
+        .and_then(|token| sessions.verify(token))
+        .unwrap_or_else(new_player_id);
+    tracing::info!("Connected: {:?} as {:?}", socket.id, player);
+    socket.extensions.insert(PlayerId(player.clone()));
+    sessions.attach(&player);
     socket.on(
         "create",
-        |socket: SocketRef, pool: State<PgPool>| async move {
-            if !socket.rooms().unwrap().is_empty() {
-                socket
-                    .emit("created-room", socket.rooms().unwrap().first())
-                    .unwrap();
+        |socket: SocketRef,
+         Data::<OrDefault<NewRoom>>(OrDefault(NewRoom {
+             rules,
+             public,
+             access,
+         })),
+         pool: State<PgPool>,
+         timers: State<Timers>,
+         matchmaking: State<Matchmaking>| async move {
+            matchmaking.cancel(socket.id.as_str());
+            if let Some(room) = current_room(&socket) {
+                socket.emit("created-room", &room).unwrap();
                 println!("{} Already in a room", socket.id);
                 return;
             }
-
-            let room = match add_room(socket.id, &pool).await {
+            let room = match add_room(&player_of(&socket), &rules, &access, false, &pool).await {
                 Err(e) => {
-                    tracing::error!("{:?}", e);
+                    tracing::warn!("{:?}", e);
+                    socket.emit("create-error", e.code()).unwrap();
                     return;
                 }
                 Ok(c) => c,
@@ -94,143 +216,1115 @@ async fn on_connect(socket: SocketRef, Data(auth): Data<AuthPayload>, pool: Stat
             tracing::info!("Creating room: {:?}", room);
             socket.leave_all().unwrap();
             socket.join(room.clone()).unwrap();
-            emit_update_room(
-                &socket,
-                &room,
-                socket.within(room.clone()).sockets().unwrap().len(),
-            );
+            emit_update_room(&socket, &room, &pool).await;
+            if public && rules.bot.is_none() && !access.is_restricted() {
+                if let Err(e) = make_public(&room, &pool).await {
+                    tracing::error!("{:?}", e);
+                }
+                emit_lobby(socket.within(LOBBY), &pool).await;
+            }
+            if rules.bot.is_some() {
+                start_game(&socket, &room, &rules, &player_of(&socket), &pool, &timers).await;
+            }
         },
     );
 
     socket.on(
         "join",
-        |socket: SocketRef, Data::<String>(room), pool: State<PgPool>| async move {
+        |socket: SocketRef,
+         Data::<JoinRequest>(request),
+         pool: State<PgPool>,
+         timers: State<Timers>,
+         matchmaking: State<Matchmaking>| async move {
+            let (room, credentials) = match request {
+                JoinRequest::Code(room) => (room, Credentials::default()),
+                JoinRequest::WithCredentials { room, credentials } => (room, credentials),
+            };
             if room.len() != ROOM_CODE_LENGTH {
                 return;
             }
+            matchmaking.cancel(socket.id.as_str());
             tracing::info!("Joining room: {:?}", room);
-            let room_error = join_room(socket.id, room.clone(), &pool).await;
+            let player = player_of(&socket);
+            let room_error = join_room(&player, room.clone(), &credentials, &pool).await;
             if let Err(e) = &room_error {
-                if let Error::RoomFull(Some(player)) = &e {
+                if let Error::RoomFull(Some(left)) = &e {
                     tracing::warn!("{:?}", e);
-                    update_sid(player, socket.id.as_str(), &pool).await.unwrap();
-                    let data = get_game_state(socket.id.as_str(), &room, &pool).await.unwrap();
-                    socket
-                        .emit(
-                            "restore",
-                            serde_json::json!({"turn": data.0, "player": data.1, "opponent": data.2, "gameover": data.3}),
-                        )
-                        .unwrap();
+                    take_seat(&room, left, &player, &pool).await.unwrap();
+                    emit_restore(&socket, &player, &room, &pool).await;
                 } else {
                     tracing::error!("{:?}", e);
+                    socket.emit("join-error", e.code()).unwrap();
                     return;
                 }
             }
             socket.leave_all().unwrap();
             socket.join(room.clone()).unwrap();
 
-            let users = socket.within(room.clone()).sockets().unwrap().len();
-            emit_update_room(&socket, &room, users);
+            let users = players_in(&socket, &room);
+            emit_update_room(&socket, &room, &pool).await;
+            emit_lobby(socket.within(LOBBY), &pool).await;
 
-            if users != 2 || room_error.is_err() {
+            if room_error.is_err() || users != 2 {
                 return;
             }
-            let ack_stream = socket
-                .within(room.clone())
-                .emit_with_ack::<Vec<Board>>("upload", ())
-                .unwrap();
-            ack_stream
-                .for_each(|(id, ack)| {
-                    let pool = pool.clone();
-                    async move {
-                        match ack {
-                            Ok(mut ack) => {
-                                if let Err(e) = add_board(id, ack.data.pop().unwrap(), &pool).await
-                                {
-                                    tracing::error!("{:?}", e);
+            let rules = match get_rules(&room, &pool).await {
+                Ok(rules) => rules,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    return;
+                }
+            };
+            start_game(&socket, &room, &rules, &player, &pool, &timers).await;
+        },
+    );
+
+    socket.on(
+        "invite",
+        |socket: SocketRef, ack: AckSender, pool: State<PgPool>| async move {
+            match create_invite(&player_of(&socket), &pool).await {
+                Ok(token) => ack.send(token).unwrap(),
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "register",
+        |socket: SocketRef,
+         Data::<Registration>(registration),
+         pool: State<PgPool>,
+         sessions: State<Sessions>| async move {
+            let player = player_of(&socket);
+            match register(&player, &registration, &pool).await {
+                Ok(account) => signed_in(&socket, &sessions, Some(account), &pool).await,
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("account-error", e.code()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "login",
+        |socket: SocketRef,
+         Data::<Login>(credentials),
+         pool: State<PgPool>,
+         sessions: State<Sessions>| async move {
+            let player = player_of(&socket);
+            match login(&player, &credentials, &pool).await {
+                Ok(account) => signed_in(&socket, &sessions, Some(account), &pool).await,
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("account-error", e.code()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "logout",
+        |socket: SocketRef, pool: State<PgPool>, sessions: State<Sessions>| async move {
+            if let Err(e) = logout(&player_of(&socket), &pool).await {
+                tracing::error!("{:?}", e);
+                return;
+            }
+            signed_in(&socket, &sessions, None, &pool).await;
+        },
+    );
+
+    socket.on(
+        "leaderboard",
+        |Data::<LeaderboardQuery>(query), ack: AckSender, pool: State<PgPool>| async move {
+            match leaderboard(&query, &pool).await {
+                Ok(leaderboard) => ack.send(leaderboard).unwrap(),
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "stats",
+        |Data::<String>(username), ack: AckSender, pool: State<PgPool>| async move {
+            match get_stats(&username, &pool).await {
+                Ok(stats) => ack.send(stats).unwrap(),
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "achievements",
+        |Data::<String>(username), ack: AckSender, pool: State<PgPool>| async move {
+            match achievements_of(&username, &pool).await {
+                Ok(achievements) => ack.send(Achievements { achievements }).unwrap(),
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "find-match",
+        |socket: SocketRef,
+         Data::<Rules>(rules),
+         pool: State<PgPool>,
+         timers: State<Timers>,
+         matchmaking: State<Matchmaking>| async move {
+            if current_room(&socket).is_some() {
+                socket
+                    .emit("match-error", Error::AlreadyInRoom.code())
+                    .unwrap();
+                return;
+            }
+            // a game against the computer needs no opponent
+            let valid = match rules.bot {
+                Some(_) => Err(Error::InvalidRules),
+                None if rules.hints => Err(Error::HintsDisabled),
+                None => rules.validate(),
+            };
+            if let Err(e) = valid {
+                tracing::warn!("{:?}", e);
+                socket.emit("match-error", e.code()).unwrap();
+                return;
+            }
+            let rating = match rating_of(&player_of(&socket), &rules, &pool).await {
+                Ok(rating) => rating,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    socket.emit("match-error", e.code()).unwrap();
+                    return;
+                }
+            };
+            match matchmaking.find(&socket, &rules, rating) {
+                Found::Opponent(opponent) => {
+                    start_match(&socket, &opponent, &rules, &pool, &timers).await
+                }
+                Found::Queued(id) => {
+                    tracing::info!("Looking for a match: {:?}", socket.id);
+                    socket.emit("queued", ()).unwrap();
+                    tokio::spawn(async move {
+                        let since = Instant::now();
+                        while since.elapsed() < MATCH_TIMEOUT {
+                            tokio::time::sleep(RATING_WINDOW_STEP).await;
+                            match matchmaking.retry(id) {
+                                Some(Found::Opponent(opponent)) => {
+                                    return start_match(&socket, &opponent, &rules, &pool, &timers)
+                                        .await
                                 }
+                                Some(Found::Queued(_)) => {}
+                                None => return,
                             }
-                            Err(err) => tracing::error!("Ack error, {}", err),
                         }
-                    }
-                })
-                .await;
-            if let Err(e) = start(socket.id, room.clone(), &pool).await {
-                tracing::error!("{:?}", e);
-                return;
+                        if matchmaking.expire(id) {
+                            socket.emit("match-timeout", ()).unwrap();
+                        }
+                    });
+                }
             }
-            tracing::info!("Game started");
-            socket
-                .within(room.clone())
-                .emit("turnover", socket.id)
-                .unwrap();
         },
     );
 
     socket.on(
-        "attack",
-        |socket: SocketRef, Data::<[usize; 2]>([i, j]), pool: State<PgPool>| async move {
-            let (hit, sunk, game_over) = match attack(socket.id, (i, j), &pool).await {
-                Ok(res) => res,
+        "cancel-match",
+        |socket: SocketRef, matchmaking: State<Matchmaking>| {
+            matchmaking.cancel(socket.id.as_str());
+        },
+    );
+
+    socket.on(
+        "resign",
+        |socket: SocketRef, pool: State<PgPool>| async move {
+            match forfeit(&player_of(&socket), &pool).await {
+                Ok((room, winner)) => {
+                    tracing::info!("Resigned in room: {:?}", room);
+                    emit_game_over(&socket, &room, &winner, Reason::Resigned, &pool).await;
+                }
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("resign-error", e.code()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "rematch-request",
+        |socket: SocketRef, pool: State<PgPool>, timers: State<Timers>| async move {
+            let room = match request_rematch(&player_of(&socket), &pool).await {
+                Ok(room) => room,
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("rematch-error", e.code()).unwrap();
+                    return;
+                }
+            };
+            tracing::info!("Rematch requested in room: {:?}", room);
+            match get_rules(&room, &pool).await {
+                // the computer is always up for another game
+                Ok(Rules { bot: Some(_), .. }) => {
+                    start_rematch(&socket, &bot::bot_id(&room), &pool, &timers).await
+                }
+                Ok(_) => socket
+                    .to(room)
+                    .emit("rematch-request", player_of(&socket))
+                    .unwrap(),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        },
+    );
+
+    socket.on(
+        "rematch-accept",
+        |socket: SocketRef, pool: State<PgPool>, timers: State<Timers>| async move {
+            start_rematch(&socket, &player_of(&socket), &pool, &timers).await;
+        },
+    );
+
+    socket.on(
+        "spectate",
+        |socket: SocketRef, Data::<JoinRequest>(request), pool: State<PgPool>| async move {
+            let (room, credentials) = match request {
+                JoinRequest::Code(room) => (room, Credentials::default()),
+                JoinRequest::WithCredentials { room, credentials } => (room, credentials),
+            };
+            let room = room.to_uppercase();
+            // seated players, in this tab or another, leave their room before watching one
+            match room_if_player_exists(&player_of(&socket), &pool).await {
+                Ok(None) => {}
+                Ok(Some(_)) => {
+                    socket
+                        .emit("spectate-error", Error::AlreadyInRoom.code())
+                        .unwrap();
+                    return;
+                }
                 Err(e) => {
                     tracing::error!("{:?}", e);
+                    socket.emit("spectate-error", e.code()).unwrap();
+                    return;
+                }
+            }
+            // restricted rooms are as closed to spectators as to players
+            if let Err(e) = check_watch(&room, &credentials, &pool).await {
+                tracing::warn!("{:?}", e);
+                socket.emit("spectate-error", e.code()).unwrap();
+                return;
+            }
+            let state = match get_spectator_state(&room, &pool).await {
+                Ok(state) => state,
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("spectate-error", e.code()).unwrap();
                     return;
                 }
             };
-            tracing::info!("Attacking at: ({}, {}), result: {:?}", i, j, hit);
+            let rules = match get_rules(&room, &pool).await {
+                Ok(rules) => rules,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    socket.emit("spectate-error", e.code()).unwrap();
+                    return;
+                }
+            };
+            tracing::info!("Spectating room: {:?}", room);
+            stop_watching(&socket, &pool).await;
+            socket.join(spectators_of(&room)).unwrap();
             socket
-                .within(socket.rooms().unwrap().first().unwrap().clone())
-                .emit(
-                    "attacked",
-                    serde_json::json!({"by": socket.id.as_str(), "at": [i, j], "hit": hit, "sunk": sunk, "game_over": game_over}),
-                )
+                .emit("spectating", Spectating { state, rules })
                 .unwrap();
+            emit_update_room(&socket, &room, &pool).await;
+        },
+    );
+
+    socket.on(
+        "chat",
+        |socket: SocketRef, Data::<String>(text), chat: State<Chat>, pool: State<PgPool>| async move {
+            // spectators only watch
+            let Some(room) = current_room(&socket) else {
+                socket.emit("chat-error", Error::NotInRoom.code()).unwrap();
+                return;
+            };
+            let player = player_of(&socket);
+            let sent = match chat.check(&player, &text) {
+                Ok(text) => save_message(&room, &player, &text, &pool).await,
+                Err(e) => Err(e),
+            };
+            match sent {
+                Ok(message) => socket.within(room).emit("chat", message).unwrap(),
+                Err(e) => {
+                    tracing::warn!("{:?}", e);
+                    socket.emit("chat-error", e.code()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "rules",
+        |Data::<String>(room), ack: AckSender, pool: State<PgPool>| async move {
+            match get_rules(&room.to_uppercase(), &pool).await {
+                Ok(rules) => ack.send(rules).unwrap(),
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "lobby",
+        |socket: SocketRef, ack: AckSender, pool: State<PgPool>| async move {
+            match get_lobby(&pool).await {
+                Ok(rooms) => {
+                    // updates follow until the socket creates or joins a room
+                    socket.join(LOBBY).unwrap();
+                    ack.send(Lobby { rooms }).unwrap();
+                }
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on("leave-lobby", |socket: SocketRef| {
+        socket.leave(LOBBY).unwrap();
+    });
+
+    socket.on(
+        "attack",
+        |socket: SocketRef,
+         Data::<[usize; 2]>([i, j]),
+         pool: State<PgPool>,
+         timers: State<Timers>| async move {
+            let Some(room) = current_room(&socket) else {
+                return;
+            };
+            let shooter = player_of(&socket);
+            if let Some(next) = fire(&socket, &room, &shooter, (i, j), &pool, &timers).await {
+                bot_turn(&socket, &room, &next, &pool, &timers).await;
+            }
+        },
+    );
+
+    socket.on(
+        "salvo",
+        |socket: SocketRef,
+         Data::<Vec<[usize; 2]>>(targets),
+         pool: State<PgPool>,
+         timers: State<Timers>| async move {
+            let Some(room) = current_room(&socket) else {
+                return;
+            };
+            let targets: Vec<_> = targets.into_iter().map(|[i, j]| (i, j)).collect();
+            fire_volley(
+                &socket,
+                &room,
+                &player_of(&socket),
+                &targets,
+                &pool,
+                &timers,
+            )
+            .await;
+        },
+    );
+
+    socket.on(
+        "hint",
+        |socket: SocketRef, ack: AckSender, pool: State<PgPool>| async move {
+            match hint(&player_of(&socket), &pool).await {
+                Ok(heatmap) => ack.send(heatmap).unwrap(),
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "replay",
+        |socket: SocketRef,
+         Data::<ReplayRequest>(request),
+         ack: AckSender,
+         pool: State<PgPool>| async move {
+            match replay(&player_of(&socket), request.game, &pool).await {
+                Ok(replay) => ack.send(replay).unwrap(),
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    ack.send(()).unwrap();
+                }
+            }
         },
     );
 
     socket.on(
         "leave",
-        |socket: SocketRef, pool: State<PgPool>| async move {
+        |socket: SocketRef, pool: State<PgPool>, matchmaking: State<Matchmaking>| async move {
             tracing::info!("Leaving Rooms: {:?}", socket.id);
+            matchmaking.cancel(socket.id.as_str());
             leave_and_inform(&socket, &pool, true).await;
         },
     );
 
-    socket.on_disconnect(|socket: SocketRef, pool: State<PgPool>| async move {
-        tracing::info!("Disconnecting: {:?}", socket.id);
-        leave_and_inform(&socket, &pool, false).await;
+    socket.on_disconnect(
+        |socket: SocketRef,
+         chat: State<Chat>,
+         matchmaking: State<Matchmaking>,
+         sessions: State<Sessions>,
+         pool: State<PgPool>| async move {
+            tracing::info!("Disconnecting: {:?}", socket.id);
+            matchmaking.cancel(socket.id.as_str());
+            let player = player_of(&socket);
+            // the player is still around in another tab
+            if !sessions.detach(&player) {
+                let rooms = [current_room(&socket), watched_room(&socket)];
+                socket.leave_all().unwrap();
+                for room in rooms.into_iter().flatten() {
+                    emit_update_room(&socket, &room, &pool).await;
+                }
+                return;
+            }
+            chat.forget(&player);
+            leave_and_inform(&socket, &pool, false).await;
+            tokio::spawn(forfeit_if_abandoned(socket, pool.0, sessions.0));
+        },
+    );
+
+    // once every handler is in place, so nothing failing here leaves the socket without them
+    let account = account_of(&player, &pool).await.unwrap_or_else(|e| {
+        tracing::error!("{:?}", e);
+        None
     });
+    emit_session(&socket, &sessions, &player, account);
+
+    match room_if_player_exists(&player, &pool).await {
+        Ok(Some(room)) => {
+            if let Err(e) = restore_sid(&player, &pool).await {
+                tracing::error!("{:?}", e);
+            }
+            emit_restore(&socket, &player, &room, &pool).await;
+            socket.join(room.clone()).ok();
+            emit_update_room(&socket, &room, &pool).await;
+            emit_lobby(socket.within(LOBBY), &pool).await;
+        }
+        Ok(None) => {}
+        Err(e) => tracing::error!("{:?}", e),
+    }
+}
+
+/// Leaves the room the socket watches, if any.
+async fn stop_watching(socket: &SocketRef, pool: &PgPool) {
+    if let Some(watched) = watched_room(socket) {
+        socket.leave(spectators_of(&watched)).unwrap();
+        emit_update_room(socket, &watched, pool).await;
+    }
 }
 
 async fn leave_and_inform(socket: &SocketRef, pool: &PgPool, delete: bool) {
-    let room = socket
-        .rooms()
-        .unwrap()
-        .first()
-        .map(|s| s.to_string())
-        .or(get_room(socket.id, pool).await.unwrap());
+    stop_watching(socket, pool).await;
+    let player = player_of(socket);
+    let room = current_room(socket).or(get_room(&player, pool).await.unwrap());
     let Some(room) = room else {
         return;
     };
-    let ops = socket.within(room.clone());
+    if delete {
+        // leaving for good gives up the game in progress
+        if let Ok((room, winner)) = forfeit(&player, pool).await {
+            emit_game_over(socket, &room, &winner, Reason::Resigned, pool).await;
+        }
+    }
     socket.leave_all().unwrap();
-    emit_update_room(socket, &room.to_string(), ops.sockets().unwrap().len());
-    let sid = socket.id.as_str();
+    emit_update_room(socket, &room, pool).await;
     if let Err(e) = if delete {
-        delete_sid(sid, pool).await
+        delete_sid(&player, pool).await
     } else {
-        to_delete_sid(sid, pool).await
+        to_delete_sid(&player, pool).await
     } {
         tracing::error!("{:?}", e);
     }
+    emit_lobby(socket.within(LOBBY), pool).await;
+}
+
+/// Ends the game of a player who has not come back within `FORFEIT_GRACE`.
+async fn forfeit_if_abandoned(socket: SocketRef, pool: PgPool, sessions: Sessions) {
+    tokio::time::sleep(FORFEIT_GRACE).await;
+    let player = player_of(&socket);
+    // players who came back are connected again, maybe more than once since
+    if sessions.is_attached(&player) || !in_delete_sid(&player, &pool).await.unwrap_or(false) {
+        return;
+    }
+    if let Ok((room, winner)) = forfeit(&player, &pool).await {
+        tracing::info!("Abandoned in room: {:?}", room);
+        emit_game_over(&socket, &room, &winner, Reason::Abandoned, &pool).await;
+    }
+}
+
+/// Collects the boards of everyone in the room and hands the first turn to `starter`.
+async fn start_game(
+    socket: &SocketRef,
+    room: &str,
+    rules: &Rules,
+    starter: &str,
+    pool: &PgPool,
+    timers: &Timers,
+) {
+    // broadcasts leave out `socket`
+    let own = socket
+        .emit_with_ack::<_, Vec<Layout>>("upload", rules)
+        .unwrap();
+    let others = socket
+        .to(room.to_string())
+        .emit_with_ack::<Vec<Layout>>("upload", rules)
+        .unwrap();
+    let uploaded = stream::select(own, others)
+        .then(|(id, ack)| {
+            let player = socket.within(room.to_string()).get_socket(id);
+            async move {
+                match player {
+                    Some(player) => save_board(&player, ack.map(|ack| ack.data), rules, pool).await,
+                    None => false,
+                }
+            }
+        })
+        .fold(true, |all, ok| async move { all && ok })
+        .await;
+    if !uploaded {
+        return;
+    }
+    if let Err(e) = start(starter, room.to_string(), pool).await {
+        tracing::error!("{:?}", e);
+        return;
+    }
+    tracing::info!("Game started");
+    emit_turnover(socket, room, starter, pool, timers).await;
+}
+
+/// Seats two players paired by the queue in a new room, with the one found waiting as its
+/// creator, and starts their game.
+async fn start_match(
+    socket: &SocketRef,
+    opponent: &SocketRef,
+    rules: &Rules,
+    pool: &PgPool,
+    timers: &Timers,
+) {
+    let player = player_of(socket);
+    let room = match add_room(&player_of(opponent), rules, &Access::default(), true, pool).await {
+        Ok(room) => room,
+        Err(e) => return match_failed(socket, opponent, e),
+    };
+    if let Err(e) = join_room(&player, room.clone(), &Credentials::default(), pool).await {
+        // the room was only for this match
+        if let Err(e) = delete_sid(&player_of(opponent), pool).await {
+            tracing::error!("{:?}", e);
+        }
+        return match_failed(socket, opponent, e);
+    }
+    tracing::info!("Matched in room: {:?}", room);
+    for player in [opponent, socket] {
+        player.leave_all().unwrap();
+        player.join(room.clone()).unwrap();
+    }
+    socket.within(room.clone()).emit("matched", &room).unwrap();
+    emit_update_room(socket, &room, pool).await;
+    start_game(socket, &room, rules, &player, pool, timers).await;
+}
+
+/// Tells both players paired by the queue that their match could not be set up.
+fn match_failed(socket: &SocketRef, opponent: &SocketRef, error: Error) {
+    tracing::error!("{:?}", error);
+    for player in [opponent, socket] {
+        player.emit("match-error", error.code()).unwrap();
+    }
+}
+
+/// Fires a shot for `shooter` and tells the room, returning who moves next while the game
+/// goes on.
+async fn fire(
+    socket: &SocketRef,
+    room: &str,
+    shooter: &str,
+    at: (usize, usize),
+    pool: &PgPool,
+    timers: &Timers,
+) -> Option<String> {
+    let (hit, sunk, game_over, next) = match attack(shooter, at, pool).await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return None;
+        }
+    };
+    tracing::info!("{} attacking at: {:?}, result: {:?}", shooter, at, hit);
+    let clock = get_clock(room, pool).await.unwrap_or(None);
+    socket
+        .within(everyone_in(room))
+        .emit(
+            "attacked",
+            Attacked {
+                by: shooter.to_string(),
+                at,
+                hit,
+                sunk,
+                game_over,
+                clock,
+            },
+        )
+        .unwrap();
+    if game_over {
+        emit_game_over(socket, room, shooter, Reason::FleetSunk, pool).await;
+        return None;
+    }
+    emit_turnover(socket, room, &next, pool, timers).await;
+    Some(next)
+}
+
+/// Fires a whole volley for `shooter` and tells the room.
+async fn fire_volley(
+    socket: &SocketRef,
+    room: &str,
+    shooter: &str,
+    targets: &[(usize, usize)],
+    pool: &PgPool,
+    timers: &Timers,
+) {
+    let (shots, game_over, next) = match salvo(shooter, targets, pool).await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return;
+        }
+    };
+    tracing::info!("{} salvo at: {:?}", shooter, targets);
+    let clock = get_clock(room, pool).await.unwrap_or(None);
+    socket
+        .within(everyone_in(room))
+        .emit(
+            "salvo",
+            Volley {
+                by: shooter.to_string(),
+                shots,
+                game_over,
+                clock,
+            },
+        )
+        .unwrap();
+    if game_over {
+        emit_game_over(socket, room, shooter, Reason::FleetSunk, pool).await;
+    } else {
+        emit_turnover(socket, room, &next, pool, timers).await;
+    }
+}
+
+/// Hands the turn to `next` with the time left, and starts timing the move in rooms with
+/// time controls.
+async fn emit_turnover(socket: &SocketRef, room: &str, next: &str, pool: &PgPool, timers: &Timers) {
+    let clock = get_clock(room, pool).await.unwrap_or(None);
+    if let Some(expires_in) = clock.as_ref().and_then(|clock| clock.expires_in()) {
+        let task = on_timeout(
+            socket.clone(),
+            room.to_string(),
+            pool.clone(),
+            timers.clone(),
+        );
+        timers.set(room, Duration::from_millis(expires_in as u64), task);
+    }
+    socket
+        .within(everyone_in(room))
+        .emit("turnover", (next, clock))
+        .unwrap();
+}
+
+/// Shoots or forfeits for the player to move once they run out of time, as the room says.
+fn on_timeout(
+    socket: SocketRef,
+    room: String,
+    pool: PgPool,
+    timers: Timers,
+) -> BoxFuture<'static, ()> {
+    Box::pin(async move {
+        let Ok(Some(clock)) = get_clock(&room, &pool).await else {
+            return;
+        };
+        let (Some(expires_in), Some(player)) = (clock.expires_in(), clock.player()) else {
+            return;
+        };
+        if expires_in > 0 {
+            // the database clock is a little behind the timer
+            let task = on_timeout(socket.clone(), room.clone(), pool.clone(), timers.clone());
+            timers.set(&room, Duration::from_millis(expires_in as u64), task);
+            return;
+        }
+        let rules = match get_rules(&room, &pool).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return;
+            }
+        };
+        let player = player.to_string();
+        tracing::info!("Out of time in room: {:?}", room);
+        match rules.time_control.map(|time| time.on_timeout) {
+            Some(OnTimeout::Forfeit) => {
+                if let Ok((room, winner)) = forfeit(&player, &pool).await {
+                    emit_game_over(&socket, &room, &winner, Reason::TimedOut, &pool).await;
+                }
+            }
+            Some(OnTimeout::RandomShot) => {
+                let targets = match random_targets(&player, &pool).await {
+                    Ok(targets) => targets,
+                    Err(e) => {
+                        tracing::error!("{:?}", e);
+                        return;
+                    }
+                };
+                match (rules.mode, targets.first()) {
+                    (Mode::Classic, Some(&at)) => {
+                        if let Some(next) = fire(&socket, &room, &player, at, &pool, &timers).await
+                        {
+                            bot_turn(&socket, &room, &next, &pool, &timers).await;
+                        }
+                    }
+                    (Mode::Salvo, _) => {
+                        fire_volley(&socket, &room, &player, &targets, &pool, &timers).await
+                    }
+                    _ => {}
+                }
+            }
+            None => {}
+        }
+    })
+}
+
+/// Lets the computer play if the turn went to it.
+async fn bot_turn(socket: &SocketRef, room: &str, next: &str, pool: &PgPool, timers: &Timers) {
+    if !is_bot(next) {
+        return;
+    }
+    match get_rules(room, pool).await {
+        Ok(Rules {
+            bot: Some(difficulty),
+            ..
+        }) => play_bot(socket, room, difficulty, pool, timers).await,
+        Ok(_) => {}
+        Err(e) => tracing::error!("{:?}", e),
+    }
+}
+
+/// Starts the next game of the room once `accepter` takes up the rematch, the computer
+/// accepting for itself.
+async fn start_rematch(socket: &SocketRef, accepter: &str, pool: &PgPool, timers: &Timers) {
+    let (room, starter) = match accept_rematch(accepter, pool).await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::warn!("{:?}", e);
+            socket.emit("rematch-error", e.code()).unwrap();
+            return;
+        }
+    };
+    let rules = match get_rules(&room, pool).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return;
+        }
+    };
+    if rules.bot.is_some() {
+        // the computer places a new fleet for every game
+        if let Err(e) = add_bot(&room, &rules, pool).await {
+            tracing::error!("{:?}", e);
+            return;
+        }
+    }
+    tracing::info!("Rematch in room: {:?}", room);
+    socket
+        .within(everyone_in(&room))
+        .emit("rematch", ())
+        .unwrap();
+    emit_update_room(socket, &room, pool).await;
+    start_game(socket, &room, &rules, &starter, pool, timers).await;
+    bot_turn(socket, &room, &starter, pool, timers).await;
+}
+
+/// Plays the computer's shots for as long as it keeps the turn.
+async fn play_bot(
+    socket: &SocketRef,
+    room: &str,
+    difficulty: Difficulty,
+    pool: &PgPool,
+    timers: &Timers,
+) {
+    let id = bot::bot_id(room);
+    loop {
+        tokio::time::sleep(BOT_DELAY).await;
+        let at = match next_shot(room, difficulty, pool).await {
+            Ok(at) => at,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return;
+            }
+        };
+        match fire(socket, room, &id, at, pool, timers).await {
+            Some(next) if next == id => {}
+            _ => return,
+        }
+    }
+}
+
+/// Saves the board a player uploaded, asking again while it is invalid.
+async fn save_board(
+    socket: &SocketRef,
+    mut uploaded: Result<Vec<Layout>, AckError<()>>,
+    rules: &Rules,
+    pool: &PgPool,
+) -> bool {
+    for attempt in 1..=UPLOAD_ATTEMPTS {
+        let result = match uploaded {
+            Ok(mut boards) => match boards.pop() {
+                Some(board) => add_board(&player_of(socket), board, pool).await,
+                None => Err(Invalid::Missing.into()),
+            },
+            Err(AckError::Serde(err)) => {
+                tracing::warn!("{:?}", err);
+                Err(Invalid::Malformed.into())
+            }
+            Err(err) => {
+                tracing::error!("Ack error, {}", err);
+                return false;
+            }
+        };
+        match result {
+            Ok(()) => return true,
+            Err(Error::InvalidBoard(e)) => {
+                tracing::warn!("{:?}", e);
+                socket.emit("board-error", &e).unwrap();
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return false;
+            }
+        }
+        if attempt == UPLOAD_ATTEMPTS {
+            break;
+        }
+        uploaded = match socket
+            .timeout(REPLACEMENT_TIME)
+            .emit_with_ack::<_, Vec<Layout>>("upload", rules)
+        {
+            Ok(ack) => ack.await.map(|ack| ack.data),
+            Err(err) => {
+                tracing::error!("{:?}", err);
+                return false;
+            }
+        };
+    }
+    // frees the seat, so the room can start with someone else instead of waiting on this player
+    tracing::warn!("No valid board from {:?}", player_of(socket));
+    socket.emit("seat-released", ()).unwrap();
+    leave_and_inform(socket, pool, true).await;
+    false
+}
+
+async fn emit_restore(socket: &SocketRef, sid: &str, room: &str, pool: &PgPool) {
+    let state = match get_game_state(sid, room, pool).await {
+        Ok(state) => Some(state),
+        // alone in the room, or placing ships
+        Err(Error::NotInRoom | Error::NoGameInProgress) => None,
+        Err(e) => return tracing::error!("{:?}", e),
+    };
+    let restore = async {
+        Ok::<_, Error>(Restore {
+            state,
+            rules: get_rules(room, pool).await?,
+            score: get_score(room, pool).await?,
+            messages: recent_messages(room, pool).await?,
+            clock: get_clock(room, pool).await?,
+        })
+    };
+    match restore.await {
+        Ok(restore) => {
+            socket.emit("restore", restore).ok();
+        }
+        Err(e) => tracing::error!("{:?}", e),
+    }
+}
+
+/// Announces the winner, reveals the fleets to spectators and sends everyone the new score.
+async fn emit_game_over(
+    socket: &SocketRef,
+    room: &str,
+    winner: &str,
+    reason: Reason,
+    pool: &PgPool,
+) {
+    socket
+        .within(everyone_in(room))
+        .emit("game-over", GameOver { winner, reason })
+        .unwrap();
+    match unlocked_in(room, pool).await {
+        Ok(unlocked) => {
+            for Unlocked {
+                player,
+                achievement,
+            } in unlocked
+            {
+                socket
+                    .within(everyone_in(room))
+                    .emit(
+                        "achievement",
+                        AchievementUnlocked {
+                            player: &player,
+                            achievement,
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+        Err(e) => tracing::error!("{:?}", e),
+    }
+    emit_spectating(socket, room, pool).await;
+    emit_update_room(socket, room, pool).await;
+}
+
+async fn emit_spectating(socket: &SocketRef, room: &str, pool: &PgPool) {
+    let snapshot = match (
+        get_spectator_state(room, pool).await,
+        get_rules(room, pool).await,
+    ) {
+        (Ok(state), Ok(rules)) => Spectating { state, rules },
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("{:?}", e);
+            return;
+        }
+    };
+    socket
+        .within(spectators_of(room))
+        .emit("spectating", snapshot)
+        .unwrap();
+}
+
+fn spectators_of(room: &str) -> String {
+    format!("{room}{SPECTATORS_SUFFIX}")
+}
+
+/// The players of the room and those watching it, for what both get to see.
+fn everyone_in(room: &str) -> [String; 2] {
+    [room.to_string(), spectators_of(room)]
+}
+
+/// Tells the socket who it plays as, with a token to come back as them, and their account.
+fn emit_session(socket: &SocketRef, sessions: &Sessions, player: &str, account: Option<Account>) {
+    socket
+        .emit(
+            "session",
+            Session {
+                player,
+                token: sessions.issue(player),
+                account,
+            },
+        )
+        .unwrap();
+}
+
+/// Sends the socket its session after signing in or out, and its room the changed names.
+async fn signed_in(
+    socket: &SocketRef,
+    sessions: &Sessions,
+    account: Option<Account>,
+    pool: &PgPool,
+) {
+    emit_session(socket, sessions, &player_of(socket), account);
+    if let Some(room) = current_room(socket) {
+        emit_update_room(socket, &room, pool).await;
+    }
+}
+
+/// The player the socket plays as, from the moment it connects.
+fn player_of(socket: &SocketRef) -> String {
+    socket
+        .extensions
+        .get::<PlayerId>()
+        .expect("every socket has a player")
+        .0
+}
+
+/// The room the socket watches, spectators are only in its spectators room.
+fn watched_room(socket: &SocketRef) -> Option<String> {
+    socket
+        .rooms()
+        .unwrap()
+        .iter()
+        .find_map(|room| room.strip_suffix(SPECTATORS_SUFFIX))
+        .map(str::to_string)
+}
+
+/// The room the socket plays in.
+fn current_room(socket: &SocketRef) -> Option<String> {
+    socket
+        .rooms()
+        .unwrap()
+        .into_iter()
+        .find(|room| !room.ends_with(SPECTATORS_SUFFIX) && room != LOBBY)
+        .map(|room| room.to_string())
+}
+
+fn players_in(socket: &SocketRef, room: &str) -> usize {
+    socket.within(room.to_string()).sockets().unwrap().len()
+}
+
+/// Sends the open public rooms to everyone browsing the lobby, reached from a socket or the
+/// server as `within(LOBBY)`.
+async fn emit_lobby(lobby: BroadcastOperators, pool: &PgPool) {
+    let browsing = lobby.sockets().unwrap();
+    if browsing.is_empty() {
+        return;
+    }
+    let lobby = match get_lobby(pool).await {
+        Ok(rooms) => Lobby { rooms },
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return;
+        }
+    };
+    for socket in browsing {
+        // they may have left while the rooms were read
+        if let Err(e) = socket.emit("lobby", &lobby) {
+            tracing::warn!("{:?}", e);
+        }
+    }
 }
 
-fn emit_update_room(socket: &SocketRef, room: &String, users: usize) {
+async fn emit_update_room(socket: &SocketRef, room: &str, pool: &PgPool) {
+    let spectators = socket.within(spectators_of(room)).sockets().unwrap().len();
+    // the room is gone once its last player has left
+    let score = get_score(room, pool).await.ok();
+    let names = display_names(room, pool).await.unwrap_or_default();
     socket
-        .within(room.clone())
+        .within(everyone_in(room))
         .emit(
             "update-room",
-            serde_json::json!({"room": &room, "users": users}),
+            RoomUpdate {
+                room,
+                users: players_in(socket, room),
+                spectators,
+                score,
+                names,
+            },
         )
         .unwrap();
 }
@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use socketioxide::extract::SocketRef;
+
+use crate::game::Rules;
+
+/// How long a player waits for an opponent before the search is given up.
+pub const MATCH_TIMEOUT: Duration = Duration::from_secs(120);
+/// Difference in rating two players are paired within right away.
+pub const RATING_WINDOW: i32 = 100;
+/// How much the window widens for every step a player waits.
+pub const RATING_WINDOW_GROWTH: i32 = 50;
+/// How often a waiting player's window widens and the queue is searched again for them.
+pub const RATING_WINDOW_STEP: Duration = Duration::from_secs(10);
+
+struct Waiting {
+    id: u64,
+    socket: SocketRef,
+    rules: Rules,
+    rating: i32,
+    since: Instant,
+}
+
+/// Outcome of looking for a match.
+pub enum Found {
+    /// An opponent was waiting and has left the queue.
+    Opponent(SocketRef),
+    /// Nobody fits yet, the player waits with this entry.
+    Queued(u64),
+}
+
+/// Players looking for a random opponent, oldest first.
+#[derive(Clone, Default)]
+pub struct Matchmaking {
+    waiting: Arc<Mutex<Vec<Waiting>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Matchmaking {
+    /// Takes the longest waiting player compatible with `rules` and `rating`, or queues the
+    /// socket, replacing any search it already had.
+    pub fn find(&self, socket: &SocketRef, rules: &Rules, rating: i32) -> Found {
+        let mut waiting = self.waiting.lock().unwrap();
+        waiting.retain(|entry| entry.socket.id != socket.id);
+        if let Some(k) = waiting.iter().position(|entry| {
+            compatible(
+                &entry.rules,
+                rules,
+                (entry.rating, rating),
+                entry.since.elapsed(),
+            )
+        }) {
+            return Found::Opponent(waiting.remove(k).socket);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        waiting.push(Waiting {
+            id,
+            socket: socket.clone(),
+            rules: rules.clone(),
+            rating,
+            since: Instant::now(),
+        });
+        Found::Queued(id)
+    }
+
+    /// Searches the queue again for the entry, whose window has widened while it waited,
+    /// returning `None` once it left the queue.
+    pub fn retry(&self, id: u64) -> Option<Found> {
+        let mut waiting = self.waiting.lock().unwrap();
+        let me = waiting.iter().position(|entry| entry.id == id)?;
+        let Some(k) = (0..waiting.len()).find(|&k| {
+            // the queue is oldest first, so the window of whoever waited longer applies
+            let (older, newer) = (&waiting[k.min(me)], &waiting[k.max(me)]);
+            k != me
+                && compatible(
+                    &older.rules,
+                    &newer.rules,
+                    (older.rating, newer.rating),
+                    older.since.elapsed(),
+                )
+        }) else {
+            return Some(Found::Queued(id));
+        };
+        let opponent = waiting.remove(k).socket;
+        waiting.retain(|entry| entry.id != id);
+        Some(Found::Opponent(opponent))
+    }
+
+    /// Takes the player out of the queue, returning whether they were in it.
+    pub fn cancel(&self, sid: &str) -> bool {
+        let mut waiting = self.waiting.lock().unwrap();
+        let before = waiting.len();
+        waiting.retain(|entry| entry.socket.id.as_str() != sid);
+        waiting.len() != before
+    }
+
+    /// Takes the entry out of the queue if it is still waiting, returning whether it was.
+    pub fn expire(&self, id: u64) -> bool {
+        let mut waiting = self.waiting.lock().unwrap();
+        let before = waiting.len();
+        waiting.retain(|entry| entry.id != id);
+        waiting.len() != before
+    }
+}
+
+/// Whether two players asking for these rules, with these ratings, can play each other once the
+/// first of them has waited for `waited`.
+fn compatible(waiting: &Rules, wanted: &Rules, ratings: (i32, i32), waited: Duration) -> bool {
+    waiting == wanted && (ratings.0 - ratings.1).abs() <= window(waited)
+}
+
+/// The difference in rating a player is paired within after waiting for `waited`.
+fn window(waited: Duration) -> i32 {
+    let steps = waited.as_secs() / RATING_WINDOW_STEP.as_secs();
+    RATING_WINDOW.saturating_add(RATING_WINDOW_GROWTH.saturating_mul(steps as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn window_widens_with_every_step() {
+        assert_eq!(window(Duration::ZERO), RATING_WINDOW);
+        assert_eq!(window(RATING_WINDOW_STEP - SECOND), RATING_WINDOW);
+        assert_eq!(
+            window(RATING_WINDOW_STEP),
+            RATING_WINDOW + RATING_WINDOW_GROWTH
+        );
+        assert_eq!(
+            window(MATCH_TIMEOUT),
+            RATING_WINDOW + 12 * RATING_WINDOW_GROWTH
+        );
+    }
+
+    #[test]
+    fn compatible_needs_the_same_rules() {
+        let classic = Rules::default();
+        let small = Rules {
+            width: 8,
+            ..Rules::default()
+        };
+        assert!(compatible(&classic, &classic, (1200, 1200), Duration::ZERO));
+        assert!(!compatible(&classic, &small, (1200, 1200), MATCH_TIMEOUT));
+    }
+
+    #[test]
+    fn compatible_ratings_grow_apart_while_waiting() {
+        let rules = Rules::default();
+        let apart = (1200, 1200 + RATING_WINDOW + RATING_WINDOW_GROWTH);
+        assert!(compatible(&rules, &rules, (1200, 1300), Duration::ZERO));
+        assert!(compatible(&rules, &rules, (1300, 1200), Duration::ZERO));
+        assert!(!compatible(&rules, &rules, apart, Duration::ZERO));
+        assert!(!compatible(
+            &rules,
+            &rules,
+            apart,
+            RATING_WINDOW_STEP - SECOND
+        ));
+        assert!(compatible(&rules, &rules, apart, RATING_WINDOW_STEP));
+    }
+}
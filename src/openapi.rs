@@ -0,0 +1,539 @@
+use std::collections::BTreeMap;
+
+use schemars::generate::{SchemaGenerator, SchemaSettings};
+use schemars::{json_schema, JsonSchema, Schema};
+use serde_json::{json, Map, Value};
+
+use crate::achievements::Progress;
+use crate::api::{status, Created, ErrorBody};
+use crate::board::Invalid;
+use crate::game::{Error, GameSummary, Mode, Replay, RoomInfo};
+use crate::ratings::{Leaderboard, LeaderboardQuery};
+use crate::stats::Stats;
+use crate::NewRoom;
+
+/// Schemas of the document, derived from the types: those served as serde writes them, named in
+/// the components, and those read as serde reads them, inline.
+struct Schemas {
+    served: SchemaGenerator,
+    read: SchemaGenerator,
+}
+
+impl Schemas {
+    fn new() -> Self {
+        let settings = SchemaSettings::draft2020_12().with(|settings| {
+            settings.meta_schema = None;
+            settings.definitions_path = "/components/schemas".into();
+        });
+        Schemas {
+            served: settings.clone().for_serialize().into_generator(),
+            read: settings
+                .for_deserialize()
+                .with(|settings| settings.inline_subschemas = true)
+                .into_generator(),
+        }
+    }
+
+    fn served<T: JsonSchema>(&mut self) -> Value {
+        self.served.subschema_for::<T>().to_value()
+    }
+
+    fn read<T: JsonSchema>(&mut self) -> Value {
+        self.read.subschema_for::<T>().to_value()
+    }
+}
+
+/// Lists the kinds of `game::Error` once, each with a sample where it holds a value, for
+/// `every_error`, and stops building when a kind is left out.
+macro_rules! every_error {
+    ($($kind:ident $(($sample:expr))?),* $(,)?) => {
+        /// One error of each kind, for their codes.
+        fn every_error() -> Vec<Error> {
+            vec![$(Error::$kind $(($sample))?),*]
+        }
+
+        const _: fn(&Error) = |error| match error {
+            $(Error::$kind { .. })|* => {}
+        };
+    };
+}
+
+every_error! {
+    RoomFull(None),
+    RoomNotFull,
+    NotYourTurn,
+    GameOverRoom,
+    AlreadyInRoom,
+    NotInRoom,
+    InvalidMove,
+    WrongMode(Mode::Classic),
+    CodeGenerationLimitReached,
+    InvalidRules,
+    RoomNotFound,
+    GameNotFound,
+    GameNotOver,
+    NoGameInProgress,
+    NoRematchRequest,
+    InvalidMessage,
+    RateLimited,
+    InvalidPassword,
+    WrongCredentials,
+    InvalidUsername,
+    UsernameTaken,
+    InvalidDisplayName,
+    AccountNotFound,
+    InvalidRequest,
+    HintsDisabled,
+    InvalidBoard(Invalid::Missing),
+    Sqlx(sqlx::Error::RowNotFound),
+}
+
+/// The code of every kind of `game::Error`, for the body of failed requests.
+pub fn error_code(_: &mut SchemaGenerator) -> Schema {
+    let codes: Vec<&str> = every_error().iter().map(Error::code).collect();
+    json_schema!({ "type": "string", "enum": codes })
+}
+
+/// Leaves `bot` out of the rooms created over HTTP, the computer only plays sockets.
+pub fn without_bot(schema: &mut Schema) {
+    if let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) {
+        properties.remove("bot");
+    }
+}
+
+fn content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// The success response of an operation, followed by the errors it can fail with, grouped by
+/// status code.
+fn responses<T: JsonSchema>(
+    schemas: &mut Schemas,
+    success: u16,
+    description: &str,
+    errors: &[Error],
+) -> Value {
+    let mut responses = Map::new();
+    responses.insert(
+        success.to_string(),
+        json!({ "description": description, "content": content(schemas.served::<T>()) }),
+    );
+    let mut by_status: BTreeMap<u16, Vec<&str>> = BTreeMap::new();
+    for error in errors
+        .iter()
+        .chain([&Error::Sqlx(sqlx::Error::RowNotFound)])
+    {
+        by_status
+            .entry(status(error).as_u16())
+            .or_default()
+            .push(error.code());
+    }
+    for (status, codes) in by_status {
+        responses.insert(
+            status.to_string(),
+            json!({ "description": codes.join(", "), "content": content(schemas.served::<ErrorBody>()) }),
+        );
+    }
+    Value::Object(responses)
+}
+
+fn path_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+/// Each property of an object as a parameter of the query string.
+fn query_parameters<T: JsonSchema>(schemas: &mut Schemas) -> Vec<Value> {
+    let Some(Value::Object(properties)) = schemas.read::<T>().get("properties").cloned() else {
+        return Vec::new();
+    };
+    properties
+        .into_iter()
+        .map(|(name, schema)| json!({ "name": name, "in": "query", "schema": schema }))
+        .collect()
+}
+
+/// The OpenAPI document of version 1 of the HTTP API.
+pub fn document() -> Value {
+    let mut c = Schemas::new();
+    let username = path_parameter("username", c.read::<String>());
+    let game = path_parameter("id", c.read::<i32>());
+    let paths = json!({
+        "/rooms": {
+            "post": {
+                "summary": "Create a room and take its first seat",
+                "security": [{}, { "bearer": [] }],
+                "requestBody": { "required": false, "content": content(c.read::<NewRoom>()) },
+                "responses": responses::<Created>(
+                    &mut c,
+                    201,
+                    "Room created, connect to the socket with the token to play in it",
+                    &[
+                        Error::InvalidRules,
+                        Error::InvalidPassword,
+                        Error::InvalidRequest,
+                        Error::AlreadyInRoom,
+                        Error::CodeGenerationLimitReached,
+                    ],
+                ),
+            },
+        },
+        "/rooms/{code}": {
+            "get": {
+                "summary": "Status, rules and score of a room",
+                "parameters": [path_parameter("code", c.read::<String>())],
+                "responses": responses::<RoomInfo>(&mut c, 200, "The room", &[Error::RoomNotFound]),
+            },
+        },
+        "/games/{id}": {
+            "get": {
+                "summary": "Public summary of a game, played or in progress",
+                "parameters": [game.clone()],
+                "responses": responses::<GameSummary>(&mut c, 200, "The game", &[Error::GameNotFound]),
+            },
+        },
+        "/games/{id}/replay": {
+            "get": {
+                "summary": "Moves of a game, with the fleets once it has ended",
+                "description": "A game in progress is only replayed to the players seated in its room.",
+                "security": [{}, { "bearer": [] }],
+                "parameters": [game],
+                "responses": responses::<Replay>(
+                    &mut c,
+                    200,
+                    "The replay",
+                    &[Error::GameNotFound, Error::NotInRoom],
+                ),
+            },
+        },
+        "/leaderboard": {
+            "get": {
+                "summary": "Best rated accounts of a variant, or those around one of them",
+                "parameters": query_parameters::<LeaderboardQuery>(&mut c),
+                "responses": responses::<Leaderboard>(
+                    &mut c,
+                    200,
+                    "The leaderboard",
+                    &[Error::InvalidRequest],
+                ),
+            },
+        },
+        "/players/{username}/stats": {
+            "get": {
+                "summary": "Stats of an account over its finished games",
+                "parameters": [username.clone()],
+                "responses": responses::<Stats>(&mut c, 200, "The stats", &[Error::AccountNotFound]),
+            },
+        },
+        "/players/{username}/achievements": {
+            "get": {
+                "summary": "The catalogue of achievements, with those an account has unlocked",
+                "parameters": [username],
+                "responses": responses::<Vec<Progress>>(
+                    &mut c,
+                    200,
+                    "The achievements",
+                    &[Error::AccountNotFound],
+                ),
+            },
+        },
+    });
+    json!({
+        "openapi": "3.1.0",
+        "info": { "title": "Battleship", "version": env!("CARGO_PKG_VERSION") },
+        "servers": [{ "url": "/api/v1" }],
+        "paths": paths,
+        "components": {
+            "schemas": c.served.take_definitions(true),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Session token issued by the server, as sent with `session`",
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::achievements::ACHIEVEMENTS;
+    use crate::game::{Move, Rules, Score, Status, Substitution, TimeControl, TurnPolicy};
+    use crate::ratings::LeaderboardEntry;
+
+    /// Checks a value against a schema of the document, its references resolved in the document.
+    fn check(doc: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+        let mut root = schema.clone();
+        root["components"] = doc["components"].clone();
+        let validator = jsonschema::draft202012::new(&root).map_err(|e| format!("{at}: {e}"))?;
+        validator
+            .validate(value)
+            .map_err(|e| format!("{at}{}: {e}", e.instance_path()))
+    }
+
+    /// Checks what a `get` of the path answers with against the document.
+    fn check_served(doc: &Value, path: &str, served: impl serde::Serialize) -> Result<(), String> {
+        let path = path.replace('~', "~0").replace('/', "~1");
+        let schema = doc
+            .pointer(&format!(
+                "/paths/{path}/get/responses/200/content/application~1json/schema"
+            ))
+            .or_else(|| {
+                doc.pointer(&format!(
+                    "/paths/{path}/post/responses/201/content/application~1json/schema"
+                ))
+            })
+            .ok_or(format!("no response for {path}"))?;
+        check(doc, schema, &serde_json::to_value(served).unwrap(), &path)
+    }
+
+    fn rules() -> Rules {
+        Rules {
+            time_control: Some(TimeControl {
+                move_limit: Some(30),
+                clock: None,
+                ..TimeControl::default()
+            }),
+            ..Rules::default()
+        }
+    }
+
+    fn stats() -> Stats {
+        Stats {
+            username: "ada".into(),
+            display_name: "Ada".into(),
+            games: 3,
+            wins: 2,
+            win_rate: Some(2.0 / 3.0),
+            average_shots_to_win: Some(41.5),
+            accuracy: None,
+            current_streak: 0,
+            longest_streak: 2,
+            favourite_first_shot: Some((4, 5)),
+        }
+    }
+
+    fn substitution() -> Substitution {
+        Substitution {
+            seat: 2,
+            player: "c".repeat(16),
+            from_move: 7,
+        }
+    }
+
+    #[test]
+    fn document_refers_only_to_its_schemas() {
+        fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+            match value {
+                Value::Object(object) => {
+                    found.extend(object.get("$ref").and_then(Value::as_str));
+                    object.values().for_each(|value| refs(value, found));
+                }
+                Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+                _ => {}
+            }
+        }
+        let doc = document();
+        let mut found = Vec::new();
+        refs(&doc, &mut found);
+        assert!(!found.is_empty());
+        for reference in found {
+            let pointer = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(
+                doc["components"]["schemas"].get(pointer).is_some(),
+                "{reference}"
+            );
+        }
+    }
+
+    #[test]
+    fn served_payloads_match_the_document() {
+        let doc = document();
+        let served = [
+            check_served(
+                &doc,
+                "/rooms",
+                Created {
+                    room: "ABCD".into(),
+                    player: "a".repeat(16),
+                    token: "token".into(),
+                },
+            ),
+            check_served(
+                &doc,
+                "/rooms/{code}",
+                RoomInfo {
+                    code: "ABCD".into(),
+                    status: Status::P2Turn,
+                    rules: rules(),
+                    score: Score {
+                        players: [Some("a".repeat(16)), None],
+                        wins: [1, 0],
+                        best_of: 5,
+                    },
+                    public: true,
+                    restricted: false,
+                    game: Some(12),
+                },
+            ),
+            check_served(
+                &doc,
+                "/games/{id}",
+                GameSummary {
+                    game: 12,
+                    room: "ABCD".into(),
+                    players: ["a".repeat(16), "b".repeat(16)],
+                    substitutions: vec![substitution()],
+                    names: [Some("Ada".into()), None],
+                    shots: [17, 16],
+                    winner: None,
+                    started_at: 1_700_000_000_000,
+                    ended_at: None,
+                },
+            ),
+            check_served(
+                &doc,
+                "/games/{id}/replay",
+                Replay {
+                    game: 12,
+                    room: "ABCD".into(),
+                    players: ["a".repeat(16), "b".repeat(16)],
+                    substitutions: vec![],
+                    boards: Some([vec!["sse".into()], vec!["hme".into()]]),
+                    winner: Some("a".repeat(16)),
+                    started_at: 1_700_000_000_000,
+                    ended_at: Some(1_700_000_100_000),
+                    moves: vec![Move {
+                        number: 1,
+                        shooter: "a".repeat(16),
+                        at: (0, 1),
+                        hit: true,
+                        sunk: Some("Destroyer".into()),
+                        time: 1_700_000_050_000,
+                    }],
+                },
+            ),
+            check_served(
+                &doc,
+                "/leaderboard",
+                Leaderboard {
+                    mode: Mode::Salvo,
+                    turn_policy: TurnPolicy::Alternate,
+                    entries: vec![LeaderboardEntry {
+                        rank: 1,
+                        username: "ada".into(),
+                        display_name: "Ada".into(),
+                        rating: 1216,
+                        games: 1,
+                    }],
+                },
+            ),
+            check_served(&doc, "/players/{username}/stats", stats()),
+            check_served(
+                &doc,
+                "/players/{username}/achievements",
+                ACHIEVEMENTS
+                    .iter()
+                    .enumerate()
+                    .map(|(k, achievement)| Progress {
+                        achievement,
+                        unlocked_at: (k % 2 == 0).then_some(1_700_000_000_000),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        ];
+        for result in served {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn every_error_matches_the_document() {
+        let doc = document();
+        let schema = &doc["components"]["schemas"]["Error"];
+        for error in every_error() {
+            let body = ErrorBody {
+                code: error.code(),
+                message: error.to_string(),
+            };
+            check(&doc, schema, &serde_json::to_value(body).unwrap(), "error").unwrap();
+        }
+    }
+
+    #[test]
+    fn mismatched_payloads_are_caught() {
+        let doc = document();
+        let stats = serde_json::to_value(stats()).unwrap();
+        let with = |name: &str, value: Value| {
+            let mut changed = stats.clone();
+            changed[name] = value;
+            changed
+        };
+        let mut missing = stats.clone();
+        missing.as_object_mut().unwrap().remove("wins");
+        for wrong in [
+            with("win_rate", json!("high")),
+            with("favourite_first_shot", json!([1, 2, 3])),
+            with("games", json!(-1.5)),
+            missing,
+        ] {
+            let schema = &doc["components"]["schemas"]["Stats"];
+            assert!(check(&doc, schema, &wrong, "stats").is_err(), "{wrong}");
+        }
+        let unknown_code = json!({ "code": "no_such_error", "message": "" });
+        assert!(check(
+            &doc,
+            &doc["components"]["schemas"]["Error"],
+            &unknown_code,
+            ""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rooms_read_by_the_server_match_the_document() {
+        let doc = document();
+        let schema = doc
+            .pointer("/paths/~1rooms/post/requestBody/content/application~1json/schema")
+            .unwrap();
+        assert!(schema["properties"].get("bot").is_none());
+        for valid in [
+            json!({}),
+            json!({ "width": 8, "height": 8, "fleet": [3, 2], "mode": "salvo", "public": true }),
+            json!({ "time_control": { "clock": 300, "on_timeout": "forfeit" } }),
+            json!({ "password": "hunter22", "invite_only": true, "hints": true, "best_of": 3 }),
+        ] {
+            check(&doc, schema, &valid, "new room").unwrap();
+            serde_json::from_value::<NewRoom>(valid).unwrap();
+        }
+        for invalid in [
+            json!({ "mode": "blitz" }),
+            json!({ "width": "8" }),
+            json!({ "time_control": { "increment": -1 } }),
+        ] {
+            assert!(
+                check(&doc, schema, &invalid, "new room").is_err(),
+                "{invalid}"
+            );
+            assert!(serde_json::from_value::<NewRoom>(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn leaderboard_parameters_are_its_query() {
+        let doc = document();
+        let parameters = doc
+            .pointer("/paths/~1leaderboard/get/parameters")
+            .and_then(Value::as_array)
+            .unwrap();
+        let query =
+            json!({ "mode": "salvo", "turn_policy": "alternate", "limit": 5, "around": "ada" });
+        assert_eq!(parameters.len(), query.as_object().unwrap().len());
+        for parameter in parameters {
+            let name = parameter["name"].as_str().unwrap();
+            check(&doc, &parameter["schema"], &query[name], name).unwrap();
+        }
+        serde_json::from_value::<LeaderboardQuery>(query).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Mode, Result, Rules, TurnPolicy};
+
+/// Rating of an account before its first rated game.
+pub const INITIAL_RATING: i32 = 1200;
+/// Most points a single game can move a rating by.
+pub const K_FACTOR: f64 = 32.0;
+pub const DEFAULT_LEADERBOARD_SIZE: i64 = 10;
+pub const MAX_LEADERBOARD_SIZE: i64 = 100;
+
+/// Which part of the leaderboard of a variant to show.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LeaderboardQuery {
+    pub mode: Mode,
+    pub turn_policy: TurnPolicy,
+    pub limit: Option<i64>,
+    /// Centres the board on this username, the top of the board when they are not rated yet.
+    pub around: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct LeaderboardEntry {
+    /// Equal ratings share a rank.
+    pub rank: i64,
+    pub username: String,
+    pub display_name: String,
+    pub rating: i32,
+    pub games: i32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Leaderboard {
+    pub mode: Mode,
+    pub turn_policy: TurnPolicy,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// The new ratings of a winner and a loser.
+fn elo(winner: i32, loser: i32) -> (i32, i32) {
+    let expected = 1.0 / (1.0 + 10f64.powf((loser - winner) as f64 / 400.0));
+    let change = (K_FACTOR * (1.0 - expected)).round() as i32;
+    (winner + change, loser - change)
+}
+
+/// Moves the ratings of the accounts behind a finished game in its variant, unless a side played without one.
+pub async fn rate_game(conn: &mut sqlx::PgConnection, game: i32) -> Result<()> {
+    let Some(result) = sqlx::query!(
+        r#"SELECT CASE WHEN g.winner = g.player1_id THEN g.account1_id ELSE g.account2_id END AS winner, CASE WHEN g.winner = g.player1_id THEN g.account2_id ELSE g.account1_id END AS loser, r.mode AS "mode: Mode", r.turn_policy AS "turn_policy: TurnPolicy" FROM games g JOIN rooms r ON r.game_id = g.id WHERE g.id = $1 AND g.winner IS NOT NULL"#,
+        game
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(());
+    };
+    let (Some(winner), Some(loser)) = (result.winner, result.loser) else {
+        return Ok(());
+    };
+
+    sqlx::query!(
+        r"INSERT INTO ratings (account_id, mode, turn_policy, rating) VALUES ($1, $3, $4, $5), ($2, $3, $4, $5) ON CONFLICT DO NOTHING",
+        winner,
+        loser,
+        result.mode as Mode,
+        result.turn_policy as TurnPolicy,
+        INITIAL_RATING
+    )
+    .execute(&mut *conn)
+    .await?;
+    let ratings = sqlx::query!(
+        r"SELECT account_id, rating FROM ratings WHERE account_id IN ($1, $2) AND mode = $3 AND turn_policy = $4 ORDER BY account_id FOR UPDATE",
+        winner,
+        loser,
+        result.mode as Mode,
+        result.turn_policy as TurnPolicy
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let rating_of = |account| {
+        ratings
+            .iter()
+            .find(|r| r.account_id == account)
+            .map_or(INITIAL_RATING, |r| r.rating)
+    };
+    let before = (rating_of(winner), rating_of(loser));
+    let after = elo(before.0, before.1);
+
+    for (account, before, after) in [(winner, before.0, after.0), (loser, before.1, after.1)] {
+        sqlx::query!(
+            r"UPDATE ratings SET rating = $4, games = games + 1, updated_at = NOW() WHERE account_id = $1 AND mode = $2 AND turn_policy = $3",
+            account,
+            result.mode as Mode,
+            result.turn_policy as TurnPolicy,
+            after
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            r"INSERT INTO rating_history (account_id, game_id, mode, turn_policy, rating_before, rating_after) VALUES ($1, $2, $3, $4, $5, $6)",
+            account,
+            game,
+            result.mode as Mode,
+            result.turn_policy as TurnPolicy,
+            before,
+            after
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The rating of the player's account in the variant of the rules, the initial one when they
+/// play without an account, have no rating yet, or the rules are not rated.
+pub async fn rating_of(sid: &str, rules: &Rules, pool: &sqlx::PgPool) -> Result<i32> {
+    if !rules.is_standard() {
+        return Ok(INITIAL_RATING);
+    }
+    let rating = sqlx::query_scalar!(
+        r"SELECT r.rating FROM ratings r JOIN logins l ON l.account_id = r.account_id WHERE l.player_id = $1 AND r.mode = $2 AND r.turn_policy = $3",
+        sid,
+        rules.mode as Mode,
+        rules.turn_policy as TurnPolicy
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rating.unwrap_or(INITIAL_RATING))
+}
+
+/// The best rated accounts of a variant, or those rated around one of them.
+pub async fn leaderboard(query: &LeaderboardQuery, pool: &sqlx::PgPool) -> Result<Leaderboard> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LEADERBOARD_SIZE)
+        .clamp(1, MAX_LEADERBOARD_SIZE);
+    let entries = sqlx::query_as!(
+        LeaderboardEntry,
+        r#"WITH ranked AS (SELECT RANK() OVER (ORDER BY r.rating DESC) AS rank, ROW_NUMBER() OVER (ORDER BY r.rating DESC, a.id) AS position, a.username, a.display_name, r.rating, r.games FROM ratings r JOIN accounts a ON a.id = r.account_id WHERE r.mode = $1 AND r.turn_policy = $4) SELECT rank AS "rank!", username, display_name, rating, games FROM ranked WHERE position > GREATEST(LEAST(COALESCE((SELECT position FROM ranked WHERE username = LOWER($2)) - $3::BIGINT / 2 - 1, 0), (SELECT COUNT(*) FROM ranked) - $3::BIGINT), 0) ORDER BY position LIMIT $3::BIGINT"#,
+        query.mode as Mode,
+        query.around.as_deref().map(str::trim),
+        limit,
+        query.turn_policy as TurnPolicy
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Leaderboard {
+        mode: query.mode,
+        turn_policy: query.turn_policy,
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_moves_as_many_points_as_it_takes() {
+        for (winner, loser) in [(1200, 1200), (1500, 1100), (1100, 1500), (2400, 800)] {
+            let (won, lost) = elo(winner, loser);
+            assert_eq!(won - winner, loser - lost);
+            assert!(won >= winner);
+        }
+    }
+
+    #[test]
+    fn elo_rewards_upsets() {
+        assert_eq!(elo(1200, 1200), (1216, 1184));
+        // a favourite gains little, an underdog almost the whole factor
+        let (favourite, _) = elo(1600, 1200);
+        let (underdog, _) = elo(1200, 1600);
+        assert_eq!(favourite - 1600, 3);
+        assert_eq!(underdog - 1200, 29);
+        assert!(elo(3000, 1000).0 - 3000 <= 1);
+        assert!(elo(1000, 3000).0 - 1000 <= K_FACTOR as i32);
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::accounts::Account;
+
+/// Length of player ids, which fit where socket ids used to.
+pub const PLAYER_ID_LENGTH: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The player a socket plays as, kept in the socket's extensions.
+#[derive(Debug, Clone)]
+pub struct PlayerId(pub String);
+
+/// Sent to a socket as it connects, the token brings it back as the same player later.
+#[derive(Debug, Serialize)]
+pub struct Session<'a> {
+    pub player: &'a str,
+    pub token: String,
+    /// The account the player is signed in to.
+    pub account: Option<Account>,
+}
+
+/// Issues and checks session tokens, and counts the sockets each player is connected with.
+#[derive(Clone)]
+pub struct Sessions {
+    key: Arc<[u8]>,
+    attached: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Sessions {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.into(),
+            attached: Default::default(),
+        }
+    }
+
+    fn mac(&self, player: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(player.as_bytes());
+        mac
+    }
+
+    /// A token of the form `player.signature`.
+    pub fn issue(&self, player: &str) -> String {
+        let signature = self.mac(player).finalize().into_bytes();
+        format!("{}.{}", player, hex::encode(signature))
+    }
+
+    /// The player the token was issued to, if it was issued by this server.
+    pub fn verify(&self, token: &str) -> Option<String> {
+        let (player, signature) = token.split_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(player).verify_slice(&signature).ok()?;
+        Some(player.to_string())
+    }
+
+    pub fn attach(&self, player: &str) {
+        *self
+            .attached
+            .lock()
+            .unwrap()
+            .entry(player.to_string())
+            .or_default() += 1;
+    }
+
+    /// Lets go of one socket of the player, returning whether it was their last.
+    pub fn detach(&self, player: &str) -> bool {
+        let mut attached = self.attached.lock().unwrap();
+        let Some(count) = attached.get_mut(player) else {
+            return true;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        attached.remove(player);
+        true
+    }
+
+    pub fn is_attached(&self, player: &str) -> bool {
+        self.attached.lock().unwrap().contains_key(player)
+    }
+}
+
+pub fn new_player_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(PLAYER_ID_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_tokens_verify_as_their_player() {
+        let sessions = Sessions::new(b"key");
+        let player = new_player_id();
+        assert_eq!(player.len(), PLAYER_ID_LENGTH);
+        assert_eq!(sessions.verify(&sessions.issue(&player)), Some(player));
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let sessions = Sessions::new(b"key");
+        let token = sessions.issue("aaaaaaaaaaaaaaaa");
+        let (_, signature) = token.split_once('.').unwrap();
+        // someone else's player id with the same signature
+        assert_eq!(
+            sessions.verify(&format!("bbbbbbbbbbbbbbbb.{signature}")),
+            None
+        );
+        let mut flipped = token.clone().into_bytes();
+        let last = flipped.last_mut().unwrap();
+        *last = if *last == b'0' { b'1' } else { b'0' };
+        assert_eq!(sessions.verify(&String::from_utf8(flipped).unwrap()), None);
+        assert_eq!(sessions.verify(&token[..token.len() - 2]), None);
+    }
+
+    #[test]
+    fn tokens_of_another_key_are_refused() {
+        let token = Sessions::new(b"other key").issue("aaaaaaaaaaaaaaaa");
+        assert_eq!(Sessions::new(b"key").verify(&token), None);
+    }
+
+    #[test]
+    fn malformed_tokens_are_refused() {
+        let sessions = Sessions::new(b"key");
+        for token in [
+            "",
+            ".",
+            "aaaaaaaaaaaaaaaa",
+            "aaaaaaaaaaaaaaaa.",
+            "aaaaaaaaaaaaaaaa.zz",
+        ] {
+            assert_eq!(sessions.verify(token), None, "{token}");
+        }
+    }
+
+    #[test]
+    fn players_stay_attached_until_their_last_tab_detaches() {
+        let sessions = Sessions::new(b"key");
+        assert!(!sessions.is_attached("a"));
+        sessions.attach("a");
+        sessions.attach("a");
+        assert!(!sessions.detach("a"));
+        assert!(sessions.is_attached("a"));
+        assert!(sessions.detach("a"));
+        assert!(!sessions.is_attached("a"));
+        // a player who never attached has nothing left open
+        assert!(sessions.detach("b"));
+    }
+}
@@ -0,0 +1,132 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::game::{Error, Result};
+
+/// What an account has done over all its finished games.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Stats {
+    pub username: String,
+    pub display_name: String,
+    pub games: i32,
+    pub wins: i32,
+    /// Wins out of games played, `None` before the first game.
+    pub win_rate: Option<f64>,
+    pub average_shots_to_win: Option<f64>,
+    /// Hits out of shots fired, `None` before the first shot.
+    pub accuracy: Option<f64>,
+    pub current_streak: i32,
+    pub longest_streak: i32,
+    /// The cell most often shot first, ties going to the first in reading order.
+    pub favourite_first_shot: Option<(usize, usize)>,
+}
+
+/// Adds a finished game to the stats of each account that played it.
+pub async fn record_game(conn: &mut sqlx::PgConnection, game: i32) -> Result<()> {
+    let Some(game) = sqlx::query!(
+        r"SELECT id, player1_id, player2_id, account1_id, account2_id, winner FROM games WHERE id = $1",
+        game
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(());
+    };
+
+    for (player, account) in [
+        (&game.player1_id, game.account1_id),
+        (&game.player2_id, game.account2_id),
+    ] {
+        let Some(account) = account else {
+            continue;
+        };
+        let won = game.winner.as_ref() == Some(player);
+        let shots = sqlx::query!(
+            r#"SELECT COUNT(*)::INT AS "shots!", (COUNT(*) FILTER (WHERE hit))::INT AS "hits!" FROM moves WHERE game_id = $1 AND shooter = $2"#,
+            game.id,
+            player
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        sqlx::query!(
+            r"INSERT INTO player_stats (account_id, games, wins, shots, hits, winning_shots, current_streak, longest_streak) VALUES ($1, 1, $2::BOOLEAN::INT, $3, $4, CASE WHEN $2 THEN $3 ELSE 0 END, $2::BOOLEAN::INT, $2::BOOLEAN::INT) ON CONFLICT (account_id) DO UPDATE SET games = player_stats.games + 1, wins = player_stats.wins + EXCLUDED.wins, shots = player_stats.shots + EXCLUDED.shots, hits = player_stats.hits + EXCLUDED.hits, winning_shots = player_stats.winning_shots + EXCLUDED.winning_shots, current_streak = CASE WHEN $2 THEN player_stats.current_streak + 1 ELSE 0 END, longest_streak = GREATEST(player_stats.longest_streak, CASE WHEN $2 THEN player_stats.current_streak + 1 ELSE 0 END), updated_at = NOW()",
+            account,
+            won,
+            shots.shots,
+            shots.hits
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            r"INSERT INTO first_shots (account_id, i, j, times) SELECT $1, i, j, 1 FROM moves WHERE game_id = $2 AND shooter = $3 ORDER BY move_number LIMIT 1 ON CONFLICT (account_id, i, j) DO UPDATE SET times = first_shots.times + 1",
+            account,
+            game.id,
+            player
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// `part` out of `whole`, `None` while there is nothing to count.
+fn ratio(part: i32, whole: i32) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
+
+pub async fn get_stats(username: &str, pool: &sqlx::PgPool) -> Result<Stats> {
+    let stats = sqlx::query!(
+        r#"SELECT a.id, a.username, a.display_name, COALESCE(s.games, 0) AS "games!", COALESCE(s.wins, 0) AS "wins!", COALESCE(s.shots, 0) AS "shots!", COALESCE(s.hits, 0) AS "hits!", COALESCE(s.winning_shots, 0) AS "winning_shots!", COALESCE(s.current_streak, 0) AS "current_streak!", COALESCE(s.longest_streak, 0) AS "longest_streak!" FROM accounts a LEFT JOIN player_stats s ON s.account_id = a.id WHERE a.username = $1"#,
+        username.trim().to_lowercase()
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(Error::AccountNotFound)?;
+    let favourite = sqlx::query!(
+        r"SELECT i, j FROM first_shots WHERE account_id = $1 ORDER BY times DESC, i, j LIMIT 1",
+        stats.id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(Stats {
+        username: stats.username,
+        display_name: stats.display_name,
+        games: stats.games,
+        wins: stats.wins,
+        win_rate: ratio(stats.wins, stats.games),
+        average_shots_to_win: ratio(stats.winning_shots, stats.wins),
+        accuracy: ratio(stats.hits, stats.shots),
+        current_streak: stats.current_streak,
+        longest_streak: stats.longest_streak,
+        favourite_first_shot: favourite.map(|f| (f.i as usize, f.j as usize)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn win_rates_are_wins_out_of_games() {
+        assert_eq!(ratio(2, 3), Some(2.0 / 3.0));
+        assert_eq!(ratio(0, 4), Some(0.0));
+        assert_eq!(ratio(5, 5), Some(1.0));
+        // before the first game
+        assert_eq!(ratio(0, 0), None);
+    }
+
+    #[test]
+    fn accuracy_is_hits_out_of_shots() {
+        assert_eq!(ratio(17, 40), Some(0.425));
+        assert_eq!(ratio(0, 12), Some(0.0));
+        assert_eq!(ratio(0, 0), None);
+    }
+
+    #[test]
+    fn average_shots_to_win_counts_only_won_games() {
+        assert_eq!(ratio(95, 2), Some(47.5));
+        // games lost so far
+        assert_eq!(ratio(0, 0), None);
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use tokio::task::AbortHandle;
+
+/// At most one pending timeout per room, replaced whenever the room's clock restarts.
+#[derive(Clone, Default)]
+pub struct Timers {
+    pending: Arc<Mutex<HashMap<String, (u64, AbortHandle)>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Timers {
+    /// Runs `task` for the room after `after`, unless another is set for the room before then.
+    pub fn set(&self, room: &str, after: Duration, task: BoxFuture<'static, ()>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (key, shared) = (room.to_string(), self.pending.clone());
+        // held until the timer is recorded, so that even an immediate one finds itself
+        let mut pending = self.pending.lock().unwrap();
+        let handle = tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            // once running the task is on its own, setting the next timer must not abort it
+            {
+                let mut pending = shared.lock().unwrap();
+                if pending.get(&key).is_some_and(|&(current, _)| current == id) {
+                    pending.remove(&key);
+                }
+            }
+            task.await;
+        })
+        .abort_handle();
+        if let Some((_, old)) = pending.insert(room.to_string(), (id, handle)) {
+            old.abort();
+        }
+    }
+}